@@ -0,0 +1,193 @@
+//! Conversions between L0 and L1 coefficients and six color ambient cubes.
+//!
+//! Ambient cubes store the irradiance for normals along the positive and negative axes.
+//! Converting coefficients to an ambient cube and back is lossless.
+//! Ambient cubes have more degrees of freedom than L0 and L1, so converting an arbitrary cube
+//! keeps the average of all faces and the differences between opposite faces
+//! but loses any differences between the averages for the X, Y, and Z axes.
+use crate::{sh, GridCoefficients};
+
+/// The linear RGB irradiance for normals along each axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AmbientCube {
+    /// The colors for the +X, -X, +Y, -Y, +Z, and -Z faces.
+    pub faces: [[f32; 3]; 6],
+}
+
+impl AmbientCube {
+    /// Creates a cube from the decompressed coefficients for a single cell.
+    /// Each face is the [irradiance](sh::irradiance) for the face's normal.
+    pub fn from_coefficients(coefficients: &[[f32; 4]; 3]) -> Self {
+        let normals = [
+            [1.0, 0.0, 0.0],
+            [-1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, -1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [0.0, 0.0, -1.0],
+        ];
+        Self {
+            faces: normals.map(|n| coefficients.map(|c| sh::irradiance(c, n))),
+        }
+    }
+
+    /// Converts the cube to coefficients for a single cell.
+    /// L0 is the average of all faces, and L1 is half the difference between opposite faces.
+    pub fn to_coefficients(&self) -> [[f32; 4]; 3] {
+        let f = &self.faces;
+        [0, 1, 2].map(|i| {
+            let l0 = f.iter().map(|face| face[i]).sum::<f32>() / 6.0;
+            [
+                (f[0][i] - f[1][i]) / 2.0,
+                (f[2][i] - f[3][i]) / 2.0,
+                (f[4][i] - f[5][i]) / 2.0,
+                l0,
+            ]
+        })
+    }
+
+    /// Evaluates the irradiance for the unit `normal` by blending the faces
+    /// with the squared normal components as weights.
+    pub fn evaluate(&self, normal: [f32; 3]) -> [f32; 3] {
+        let mut result = [0.0; 3];
+        for (axis, n) in normal.iter().enumerate() {
+            let face = if *n >= 0.0 { axis * 2 } else { axis * 2 + 1 };
+            for (r, c) in result.iter_mut().zip(self.faces[face]) {
+                *r += n * n * c;
+            }
+        }
+        result
+    }
+}
+
+impl GridCoefficients {
+    /// Converts the coefficients for each cell to an [AmbientCube].
+    pub fn to_ambient_cubes(&self) -> Vec<AmbientCube> {
+        self.coefficients
+            .iter()
+            .map(AmbientCube::from_coefficients)
+            .collect()
+    }
+
+    /// Creates a grid with the coefficients for each cell from an [AmbientCube].
+    /// The `unk5` and `unk6` values are calculated to fit the coefficients.
+    pub fn from_ambient_cubes(
+        grid_cell_count_xyz: [u32; 3],
+        grid_range_min_xyz: [f32; 3],
+        grid_range_max_xyz: [f32; 3],
+        cubes: &[AmbientCube],
+    ) -> Self {
+        let mut grid = Self {
+            grid_cell_count_xyz,
+            grid_range_min_xyz,
+            grid_range_max_xyz,
+            unk5: 0.0,
+            unk6: 0.0,
+            coefficients: cubes.iter().map(|c| c.to_coefficients()).collect(),
+        };
+        grid.recalculate_unk5_unk6();
+        grid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::grid;
+
+    #[test]
+    fn from_coefficients_faces() {
+        let coefficients = [
+            [0.1, 0.2, 0.3, 1.0],
+            [0.0, 0.0, 0.0, 0.5],
+            [-0.25, 0.0, 0.5, 0.0],
+        ];
+        let cube = AmbientCube::from_coefficients(&coefficients);
+        assert_eq!(
+            [
+                [1.1, 0.5, -0.25],
+                [0.9, 0.5, 0.25],
+                [1.2, 0.5, 0.0],
+                [0.8, 0.5, 0.0],
+                [1.3, 0.5, 0.5],
+                [0.7, 0.5, -0.5],
+            ],
+            cube.faces
+        );
+    }
+
+    #[test]
+    fn coefficients_round_trip() {
+        let coefficients = [
+            [0.1, 0.2, 0.3, 1.0],
+            [0.0, 0.0, 0.0, 0.5],
+            [-0.25, 0.0, 0.5, 0.0],
+        ];
+        let cube = AmbientCube::from_coefficients(&coefficients);
+        let result = cube.to_coefficients();
+        for (a, b) in coefficients.iter().flatten().zip(result.iter().flatten()) {
+            assert!((a - b).abs() < 0.0001, "{:?} != {:?}", coefficients, result);
+        }
+    }
+
+    #[test]
+    fn to_coefficients_uniform() {
+        let cube = AmbientCube {
+            faces: [[0.25, 0.5, 1.0]; 6],
+        };
+        assert_eq!(
+            [
+                [0.0, 0.0, 0.0, 0.25],
+                [0.0, 0.0, 0.0, 0.5],
+                [0.0, 0.0, 0.0, 1.0]
+            ],
+            cube.to_coefficients()
+        );
+    }
+
+    #[test]
+    fn to_coefficients_single_face() {
+        // Only the +Y face is lit.
+        let mut faces = [[0.0; 3]; 6];
+        faces[2] = [6.0; 3];
+        let cube = AmbientCube { faces };
+        assert_eq!([[0.0, 3.0, 0.0, 1.0]; 3], cube.to_coefficients());
+    }
+
+    #[test]
+    fn evaluate_faces_and_diagonal() {
+        let cube = AmbientCube {
+            faces: [
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 0.0, 1.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 1.0, 1.0],
+                [1.0, 1.0, 1.0],
+            ],
+        };
+        assert_eq!([1.0, 0.0, 0.0], cube.evaluate([1.0, 0.0, 0.0]));
+        assert_eq!([1.0, 1.0, 1.0], cube.evaluate([0.0, 0.0, -1.0]));
+
+        let d = 0.5f32.sqrt();
+        let result = cube.evaluate([-d, d, 0.0]);
+        for (a, b) in result.iter().zip([0.0, 0.5, 0.5]) {
+            assert!((a - b).abs() < 0.0001);
+        }
+    }
+
+    #[test]
+    fn grid_ambient_cubes_round_trip() {
+        let grid = grid(
+            [2, 1, 1],
+            vec![[[0.0, 0.5, 0.0, 0.75]; 3], [[0.25, 0.0, -0.5, 1.0]; 3]],
+        );
+        let cubes = grid.to_ambient_cubes();
+        assert_eq!(2, cubes.len());
+
+        let new_grid =
+            GridCoefficients::from_ambient_cubes([2, 1, 1], [0.0; 3], [1.0, 0.0, 0.0], &cubes);
+        assert_eq!(grid.coefficients, new_grid.coefficients);
+        assert_ne!(0.0, new_grid.unk6);
+    }
+}
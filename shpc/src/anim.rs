@@ -0,0 +1,498 @@
+//! Operations on the TPCB timeline of a [ShanFile].
+//! The coefficients are assumed to be linearly interpolated between TPCBs.
+use crate::{GridCoefficients, ShanFile, TpcbData};
+
+/// The interpolation curve between key grids for [ShanFile::from_keyframes].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps the linear factor `t` in the range `0.0..=1.0` onto the curve.
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// The placement of intermediate TPCBs for [ShanFile::from_keyframes].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyframeSpacing {
+    /// Evenly spaced TPCBs between each pair of key grids.
+    Even,
+    /// Additional TPCBs only where the curve deviates the most from linear interpolation.
+    Adaptive,
+}
+
+impl ShanFile {
+    /// The coefficients at `frame` interpolated from the surrounding TPCBs.
+    /// Frames before the first or after the last TPCB use the nearest TPCB.
+    /// Returns `None` if there are no TPCBs.
+    pub fn coefficients_at(&self, frame: f32) -> Option<GridCoefficients> {
+        // Files converted from JSON or CSV aren't guaranteed to be sorted by frame.
+        let previous = self
+            .tpcbs
+            .iter()
+            .filter(|t| t.starting_frame as f32 <= frame)
+            .max_by_key(|t| t.starting_frame);
+        let next = self
+            .tpcbs
+            .iter()
+            .filter(|t| t.starting_frame as f32 > frame)
+            .min_by_key(|t| t.starting_frame);
+
+        match (previous, next) {
+            (Some(previous), Some(next))
+                if next.starting_frame > previous.starting_frame
+                    && frame > previous.starting_frame as f32 =>
+            {
+                let t = (frame - previous.starting_frame as f32)
+                    / (next.starting_frame - previous.starting_frame) as f32;
+                Some(previous.coefficients.lerp(&next.coefficients, t))
+            }
+            (Some(nearest), _) | (None, Some(nearest)) => Some(nearest.coefficients.clone()),
+            (None, None) => None,
+        }
+    }
+
+    /// Blends `self` at `t = 0.0` with `other` at `t = 1.0`.
+    ///
+    /// The result has a TPCB for each starting frame in either file.
+    /// Frames missing from one of the files are interpolated from its surrounding TPCBs.
+    /// Grids with different cell counts or bounds are resampled to a common grid.
+    pub fn blend(&self, other: &Self, t: f32) -> Self {
+        let mut frames: Vec<_> = self
+            .tpcbs
+            .iter()
+            .chain(other.tpcbs.iter())
+            .map(|tpcb| tpcb.starting_frame)
+            .collect();
+        frames.sort_unstable();
+        frames.dedup();
+
+        let tpcbs = frames
+            .into_iter()
+            .filter_map(|frame| {
+                let a = self.coefficients_at(frame as f32);
+                let b = other.coefficients_at(frame as f32);
+                let coefficients = match (a, b) {
+                    (Some(a), Some(b)) => a.lerp(&b, t),
+                    (a, b) => a.or(b)?,
+                };
+                Some(TpcbData {
+                    starting_frame: frame,
+                    coefficients,
+                })
+            })
+            .collect();
+
+        Self {
+            name: self.name.clone(),
+            tpcbs,
+        }
+    }
+
+    /// Removes TPCBs that can be reconstructed by linearly interpolating the remaining TPCBs
+    /// with an [irradiance difference](GridCoefficients::max_irradiance_difference)
+    /// of at most `tolerance`.
+    /// The first and last TPCBs are always kept to preserve the length of the animation.
+    /// Returns the number of removed TPCBs.
+    pub fn reduce_keyframes(&mut self, tolerance: f32) -> usize {
+        if self.tpcbs.len() <= 2 {
+            return 0;
+        }
+
+        let last = self.tpcbs.len() - 1;
+        let mut kept = vec![0];
+        let mut previous = 0;
+        for i in 1..last {
+            // Skipping i should also preserve any skipped TPCBs since the previous kept TPCB.
+            let can_remove = (previous + 1..=i)
+                .all(|j| self.interpolation_error(previous, i + 1, j) <= tolerance);
+            if !can_remove {
+                kept.push(i);
+                previous = i;
+            }
+        }
+        kept.push(last);
+
+        let removed = self.tpcbs.len() - kept.len();
+        let mut index = 0;
+        self.tpcbs.retain(|_| {
+            let keep = kept.binary_search(&index).is_ok();
+            index += 1;
+            keep
+        });
+        removed
+    }
+
+    // The error from reconstructing the TPCB at index by interpolating from start to end.
+    fn interpolation_error(&self, start: usize, end: usize, index: usize) -> f32 {
+        let start = &self.tpcbs[start];
+        let end = &self.tpcbs[end];
+        let tpcb = &self.tpcbs[index];
+
+        let length = end.starting_frame.saturating_sub(start.starting_frame);
+        let t = if length > 0 {
+            tpcb.starting_frame.saturating_sub(start.starting_frame) as f32 / length as f32
+        } else {
+            0.0
+        };
+        start
+            .coefficients
+            .lerp(&end.coefficients, t)
+            .max_irradiance_difference(&tpcb.coefficients)
+    }
+
+    /// Generates an animation from `keyframes` interpolated along the `easing` curve.
+    ///
+    /// The game linearly interpolates between TPCBs,
+    /// so intermediate TPCBs are added between the key grids until the
+    /// [irradiance difference](GridCoefficients::max_irradiance_difference)
+    /// from the continuous curve is at most `max_error`.
+    /// Key grids with different cell counts or bounds are resampled to a common grid.
+    /// [KeyframeSpacing::Even] uses at most 1024 TPCBs between each pair of key grids.
+    pub fn from_keyframes(
+        name: String,
+        keyframes: &[TpcbData],
+        easing: Easing,
+        spacing: KeyframeSpacing,
+        max_error: f32,
+    ) -> Self {
+        let mut keyframes = keyframes.to_vec();
+        keyframes.sort_by_key(|k| k.starting_frame);
+        keyframes.dedup_by_key(|k| k.starting_frame);
+
+        if let Some(first) = keyframes.first() {
+            let first = first.coefficients.clone();
+            if !keyframes
+                .iter()
+                .all(|k| k.coefficients.has_same_layout(&first))
+            {
+                let layout = keyframes
+                    .iter()
+                    .fold(first, |layout, k| layout.union_layout(&k.coefficients));
+                for k in &mut keyframes {
+                    k.coefficients = k.coefficients.resample(&layout);
+                }
+            }
+        }
+
+        let curve = Curve {
+            keyframes: &keyframes,
+            easing,
+        };
+
+        let mut frames = Vec::new();
+        for segment in keyframes.windows(2) {
+            let start = segment[0].starting_frame;
+            let end = segment[1].starting_frame;
+            match spacing {
+                KeyframeSpacing::Even => curve.even_frames(start, end, max_error, &mut frames),
+                KeyframeSpacing::Adaptive => {
+                    curve.adaptive_frames(start, end, max_error, &mut frames)
+                }
+            }
+        }
+        if let Some(last) = keyframes.last() {
+            frames.push(last.starting_frame);
+        }
+
+        Self {
+            name,
+            tpcbs: frames
+                .into_iter()
+                .map(|frame| TpcbData {
+                    starting_frame: frame,
+                    coefficients: curve.evaluate(frame),
+                })
+                .collect(),
+        }
+    }
+}
+
+// The maximum number of intermediate frames checked for the error of each segment.
+const MAX_ERROR_SAMPLES: u64 = 64;
+
+// The maximum number of evenly spaced TPCBs between each pair of key grids.
+const MAX_EVEN_COUNT: u32 = 1024;
+
+// The continuous animation curve through sorted keyframes with a common layout.
+struct Curve<'a> {
+    keyframes: &'a [TpcbData],
+    easing: Easing,
+}
+
+impl<'a> Curve<'a> {
+    fn evaluate(&self, frame: u32) -> GridCoefficients {
+        let next = self
+            .keyframes
+            .partition_point(|k| k.starting_frame <= frame);
+        let previous = &self.keyframes[next.saturating_sub(1)];
+        match self.keyframes.get(next) {
+            Some(next)
+                if next.starting_frame > previous.starting_frame
+                    && frame > previous.starting_frame =>
+            {
+                let t = (frame - previous.starting_frame) as f32
+                    / (next.starting_frame - previous.starting_frame) as f32;
+                previous
+                    .coefficients
+                    .lerp(&next.coefficients, self.easing.apply(t))
+            }
+            _ => previous.coefficients.clone(),
+        }
+    }
+
+    // The maximum error from linearly interpolating the curve between start and end.
+    fn linear_error(&self, start: u32, end: u32) -> f32 {
+        let a = self.evaluate(start);
+        let b = self.evaluate(end);
+
+        // Check every intermediate frame for short segments and evenly spaced frames otherwise.
+        let length = (end - start) as u64;
+        let samples = length.saturating_sub(1).min(MAX_ERROR_SAMPLES);
+        (1..=samples)
+            .map(|i| start + (length * i / (samples + 1)) as u32)
+            .map(|frame| {
+                let t = (frame - start) as f32 / length as f32;
+                self.evaluate(frame)
+                    .max_irradiance_difference(&a.lerp(&b, t))
+            })
+            .fold(0.0, f32::max)
+    }
+
+    // Add the starting frames for the smallest number of evenly spaced TPCBs within max_error.
+    fn even_frames(&self, start: u32, end: u32, max_error: f32, frames: &mut Vec<u32>) {
+        let length = end - start;
+        let frame = |i: u32, count: u32| start + (length as u64 * i as u64 / count as u64) as u32;
+        let is_within_error = |count: u32| {
+            (0..count).all(|i| self.linear_error(frame(i, count), frame(i + 1, count)) <= max_error)
+        };
+
+        // Double the count until the error is small enough before searching for the smallest count.
+        // The maximum count is used if the error threshold can't be reached.
+        let max_count = length.clamp(1, MAX_EVEN_COUNT);
+        let mut lower = 1;
+        let mut upper = 1;
+        while upper < max_count && !is_within_error(upper) {
+            lower = upper + 1;
+            upper = (upper * 2).min(max_count);
+        }
+        while lower < upper {
+            let middle = lower + (upper - lower) / 2;
+            if is_within_error(middle) {
+                upper = middle;
+            } else {
+                lower = middle + 1;
+            }
+        }
+
+        frames.extend((0..upper).map(|i| frame(i, upper)));
+    }
+
+    // Recursively split the segment in half until the error is within max_error.
+    fn adaptive_frames(&self, start: u32, end: u32, max_error: f32, frames: &mut Vec<u32>) {
+        if end - start <= 1 || self.linear_error(start, end) <= max_error {
+            frames.push(start);
+        } else {
+            let middle = start + (end - start) / 2;
+            self.adaptive_frames(start, middle, max_error, frames);
+            self.adaptive_frames(middle, end, max_error, frames);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{grid, shan_file};
+
+    fn keyframe_file(keyframes: &[(u32, f32)]) -> ShanFile {
+        shan_file(
+            keyframes
+                .iter()
+                .map(|(frame, value)| (*frame, grid([1, 1, 1], vec![[[0.0, 0.0, 0.0, *value]; 3]])))
+                .collect(),
+        )
+    }
+
+    fn l0(grid: &GridCoefficients) -> f32 {
+        grid.coefficients[0][0][3]
+    }
+
+    #[test]
+    fn coefficients_at_frames() {
+        let file = keyframe_file(&[(0, 0.0), (10, 1.0), (30, 3.0)]);
+        assert_eq!(0.0, l0(&file.coefficients_at(-5.0).unwrap()));
+        assert_eq!(0.0, l0(&file.coefficients_at(0.0).unwrap()));
+        assert_eq!(0.5, l0(&file.coefficients_at(5.0).unwrap()));
+        assert_eq!(1.0, l0(&file.coefficients_at(10.0).unwrap()));
+        assert_eq!(2.0, l0(&file.coefficients_at(20.0).unwrap()));
+        assert_eq!(3.0, l0(&file.coefficients_at(100.0).unwrap()));
+        assert_eq!(None, keyframe_file(&[]).coefficients_at(0.0));
+    }
+
+    #[test]
+    fn coefficients_at_unsorted_frames() {
+        let file = keyframe_file(&[(30, 3.0), (0, 0.0), (10, 1.0)]);
+        assert_eq!(0.0, l0(&file.coefficients_at(-5.0).unwrap()));
+        assert_eq!(0.5, l0(&file.coefficients_at(5.0).unwrap()));
+        assert_eq!(2.0, l0(&file.coefficients_at(20.0).unwrap()));
+        assert_eq!(3.0, l0(&file.coefficients_at(100.0).unwrap()));
+    }
+
+    #[test]
+    fn blend_merges_timelines() {
+        let day = keyframe_file(&[(0, 0.0), (20, 2.0)]);
+        let night = keyframe_file(&[(10, 10.0)]);
+
+        let blended = day.blend(&night, 0.5);
+        let frames: Vec<_> = blended.tpcbs.iter().map(|t| t.starting_frame).collect();
+        assert_eq!(vec![0, 10, 20], frames);
+
+        let values: Vec<_> = blended.tpcbs.iter().map(|t| l0(&t.coefficients)).collect();
+        assert_eq!(vec![5.0, 5.5, 6.0], values);
+    }
+
+    #[test]
+    fn blend_empty_file() {
+        let day = keyframe_file(&[(0, 1.0)]);
+        let blended = day.blend(&keyframe_file(&[]), 0.5);
+        assert_eq!(day, blended);
+    }
+
+    #[test]
+    fn easing_endpoints() {
+        for easing in [
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+        ] {
+            assert_eq!(0.0, easing.apply(0.0));
+            assert_eq!(1.0, easing.apply(1.0));
+        }
+        assert_eq!(0.25, Easing::EaseIn.apply(0.5));
+        assert_eq!(0.75, Easing::EaseOut.apply(0.5));
+    }
+
+    #[test]
+    fn from_keyframes_linear() {
+        // Linear keyframes don't need any intermediate TPCBs.
+        let keyframes = keyframe_file(&[(0, 0.0), (3600, 1.0), (7200, 0.0)]).tpcbs;
+        for spacing in [KeyframeSpacing::Even, KeyframeSpacing::Adaptive] {
+            let file = ShanFile::from_keyframes(
+                "chara".to_string(),
+                &keyframes,
+                Easing::Linear,
+                spacing,
+                0.001,
+            );
+            assert_eq!(keyframes, file.tpcbs);
+        }
+    }
+
+    #[test]
+    fn from_keyframes_even() {
+        let keyframes = keyframe_file(&[(0, 0.0), (100, 1.0)]).tpcbs;
+        let file = ShanFile::from_keyframes(
+            "chara".to_string(),
+            &keyframes,
+            Easing::EaseIn,
+            KeyframeSpacing::Even,
+            0.02,
+        );
+        let frames: Vec<_> = file.tpcbs.iter().map(|t| t.starting_frame).collect();
+        assert_eq!(vec![0, 25, 50, 75, 100], frames);
+        assert_eq!(0.25, l0(&file.tpcbs[2].coefficients));
+    }
+
+    #[test]
+    fn from_keyframes_even_max_count() {
+        // The threshold can't be reached, so use the maximum count.
+        let keyframes = keyframe_file(&[(0, 0.0), (7200, 1.0)]).tpcbs;
+        let file = ShanFile::from_keyframes(
+            "chara".to_string(),
+            &keyframes,
+            Easing::EaseInOut,
+            KeyframeSpacing::Even,
+            -1.0,
+        );
+        assert_eq!(MAX_EVEN_COUNT as usize + 1, file.tpcbs.len());
+        assert_eq!(7200, file.tpcbs.last().unwrap().starting_frame);
+    }
+
+    #[test]
+    fn from_keyframes_adaptive() {
+        let keyframes = keyframe_file(&[(0, 0.0), (100, 1.0)]).tpcbs;
+        let file = ShanFile::from_keyframes(
+            "chara".to_string(),
+            &keyframes,
+            Easing::EaseIn,
+            KeyframeSpacing::Adaptive,
+            0.01,
+        );
+        // Every segment should be within the error threshold.
+        let curve = Curve {
+            keyframes: &keyframes,
+            easing: Easing::EaseIn,
+        };
+        for segment in file.tpcbs.windows(2) {
+            assert!(
+                curve.linear_error(segment[0].starting_frame, segment[1].starting_frame) <= 0.01
+            );
+        }
+        assert_eq!(100, file.tpcbs.last().unwrap().starting_frame);
+    }
+
+    #[test]
+    fn reduce_keyframes_linear() {
+        let mut file = keyframe_file(&[(0, 0.0), (10, 1.0), (20, 2.0), (30, 3.5), (40, 5.0)]);
+        assert_eq!(2, file.reduce_keyframes(0.001));
+        let frames: Vec<_> = file.tpcbs.iter().map(|t| t.starting_frame).collect();
+        assert_eq!(vec![0, 20, 40], frames);
+    }
+
+    #[test]
+    fn reduce_keyframes_tolerance() {
+        let mut file = keyframe_file(&[(0, 0.0), (10, 1.1), (20, 2.0)]);
+        assert_eq!(0, file.reduce_keyframes(0.05));
+        assert_eq!(1, file.reduce_keyframes(0.2));
+        assert_eq!(2, file.tpcbs.len());
+    }
+
+    #[test]
+    fn reduce_keyframes_shan_fields() {
+        // xeno_gaur has near identical TPCBs at several frames.
+        let mut file = keyframe_file(&[
+            (0, 1.0),
+            (2800, 1.0),
+            (3000, 1.0),
+            (3400, 2.0),
+            (3600, 2.0),
+            (6400, 2.0),
+            (6600, 1.0),
+            (7000, 1.0),
+            (7200, 1.0),
+        ]);
+        assert_eq!(3, file.reduce_keyframes(0.001));
+
+        let shan = crate::shan::Shan::try_from(&file).unwrap();
+        assert_eq!(7200, shan.unk1);
+        assert_eq!(6, shan.tpcb_count);
+        assert_eq!(
+            vec![0, 3000, 3400, 6400, 6600, 7200],
+            shan.tpcb_starting_frames
+        );
+    }
+}
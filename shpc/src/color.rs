@@ -0,0 +1,381 @@
+//! Color adjustments for spherical harmonic coefficients.
+//!
+//! Adjustments are applied to the decompressed coefficients,
+//! and the `unk5` and `unk6` values are recalculated to fit the new range before recompression.
+//! Most adjustments are linear and apply the same 3x3 color matrix to each coefficient.
+use std::path::Path;
+
+use crate::{GridCoefficients, ShanFile};
+
+/// Rec. 709 luminance weights for linear RGB.
+pub const LUMINANCE_WEIGHTS: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+/// The luminance of a linear RGB color.
+pub fn luminance(rgb: [f32; 3]) -> f32 {
+    rgb[0] * LUMINANCE_WEIGHTS[0] + rgb[1] * LUMINANCE_WEIGHTS[1] + rgb[2] * LUMINANCE_WEIGHTS[2]
+}
+
+/// A 3x3 matrix for transforming RGB colors where each row computes an output channel.
+pub type ColorMatrix = [[f32; 3]; 3];
+
+/// A color matrix that scales each channel by `rgb`.
+pub fn tint_matrix(rgb: [f32; 3]) -> ColorMatrix {
+    [[rgb[0], 0.0, 0.0], [0.0, rgb[1], 0.0], [0.0, 0.0, rgb[2]]]
+}
+
+/// A color matrix that interpolates between grayscale at `0.0`
+/// and the original colors at `1.0` while preserving luminance.
+pub fn saturation_matrix(saturation: f32) -> ColorMatrix {
+    let mut matrix = [[0.0; 3]; 3];
+    for (i, row) in matrix.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (1.0 - saturation) * LUMINANCE_WEIGHTS[j];
+            if i == j {
+                *value += saturation;
+            }
+        }
+    }
+    matrix
+}
+
+/// A color matrix that rotates hues by `degrees` around the gray axis.
+pub fn hue_shift_matrix(degrees: f32) -> ColorMatrix {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let a = cos + (1.0 - cos) / 3.0;
+    let b = (1.0 - cos) / 3.0 - (1.0f32 / 3.0).sqrt() * sin;
+    let c = (1.0 - cos) / 3.0 + (1.0f32 / 3.0).sqrt() * sin;
+    [[a, b, c], [c, a, b], [b, c, a]]
+}
+
+/// A 3D color lookup table loaded from an Adobe/Resolve .cube file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CubeLut {
+    pub size: usize,
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+    /// The output colors for `size * size * size` inputs with red changing fastest.
+    pub data: Vec<[f32; 3]>,
+}
+
+impl CubeLut {
+    /// Tries to read and parse the .cube file from `path`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text)
+    }
+
+    /// Tries to parse the text of a .cube file.
+    /// Only 3D tables are supported.
+    pub fn parse(text: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut data = Vec::new();
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with("TITLE") {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            match parts.next() {
+                Some("LUT_3D_SIZE") => {
+                    size = Some(parts.next().ok_or("Missing LUT_3D_SIZE value")?.parse()?)
+                }
+                Some("LUT_1D_SIZE") => return Err("1D LUTs are not supported".into()),
+                Some("DOMAIN_MIN") => domain_min = parse_rgb(parts)?,
+                Some("DOMAIN_MAX") => domain_max = parse_rgb(parts)?,
+                Some("LUT_3D_INPUT_RANGE") => {
+                    let min = parts.next().ok_or("Missing LUT_3D_INPUT_RANGE min")?;
+                    let max = parts.next().ok_or("Missing LUT_3D_INPUT_RANGE max")?;
+                    domain_min = [min.parse()?; 3];
+                    domain_max = [max.parse()?; 3];
+                }
+                // Skip unsupported keywords since data rows only contain numbers.
+                Some(keyword) if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => (),
+                Some(_) => data.push(parse_rgb(line.split_whitespace())?),
+                None => (),
+            }
+        }
+
+        let size: usize = size.ok_or("Missing LUT_3D_SIZE")?;
+        let entry_count = size
+            .checked_pow(3)
+            .ok_or_else(|| format!("LUT_3D_SIZE {} is too large", size))?;
+        if size < 2 || data.len() != entry_count {
+            return Err(format!(
+                "Expected {} entries for LUT_3D_SIZE {} but found {}",
+                entry_count,
+                size,
+                data.len()
+            )
+            .into());
+        }
+
+        Ok(Self {
+            size,
+            domain_min,
+            domain_max,
+            data,
+        })
+    }
+
+    /// Trilinearly interpolates the table at `rgb`.
+    /// Inputs outside the domain are clamped.
+    pub fn sample(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let max_index = self.size - 1;
+
+        let mut neighbors = [[0usize; 2]; 3];
+        let mut factors = [0.0f32; 3];
+        for i in 0..3 {
+            let range = self.domain_max[i] - self.domain_min[i];
+            let t = if range > 0.0 {
+                ((rgb[i] - self.domain_min[i]) / range).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let f = t * max_index as f32;
+            let i0 = (f.floor() as usize).min(max_index - 1);
+            neighbors[i] = [i0, i0 + 1];
+            factors[i] = f - i0 as f32;
+        }
+
+        let mut result = [0.0; 3];
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut index = 0;
+            let mut stride = 1;
+            for i in 0..3 {
+                let upper = (corner >> i) & 1;
+                index += neighbors[i][upper] * stride;
+                stride *= self.size;
+                weight *= if upper == 1 {
+                    factors[i]
+                } else {
+                    1.0 - factors[i]
+                };
+            }
+
+            for (r, v) in result.iter_mut().zip(self.data[index].iter()) {
+                *r += v * weight;
+            }
+        }
+        result
+    }
+}
+
+fn parse_rgb<'a, I: Iterator<Item = &'a str>>(
+    mut parts: I,
+) -> Result<[f32; 3], Box<dyn std::error::Error>> {
+    let mut rgb = [0.0; 3];
+    for value in rgb.iter_mut() {
+        *value = parts.next().ok_or("Expected 3 color values")?.parse()?;
+    }
+    Ok(rgb)
+}
+
+impl GridCoefficients {
+    /// Transforms the color of every L0 and L1 coefficient by `matrix`.
+    pub fn apply_color_matrix(&mut self, matrix: ColorMatrix) {
+        for c in &mut self.coefficients {
+            for i in 0..4 {
+                let rgb = [c[0][i], c[1][i], c[2][i]];
+                for (channel, row) in c.iter_mut().zip(matrix.iter()) {
+                    channel[i] = row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2];
+                }
+            }
+        }
+        self.recalculate_unk5_unk6();
+    }
+
+    /// Scales the overall brightness by `multiplier`.
+    pub fn adjust_exposure(&mut self, multiplier: f32) {
+        self.apply_color_matrix(tint_matrix([multiplier; 3]));
+    }
+
+    /// Scales the red, green, and blue channels by the values in `rgb`.
+    pub fn tint(&mut self, rgb: [f32; 3]) {
+        self.apply_color_matrix(tint_matrix(rgb));
+    }
+
+    /// Adjusts the saturation where `0.0` is grayscale and `1.0` is unchanged.
+    pub fn adjust_saturation(&mut self, saturation: f32) {
+        self.apply_color_matrix(saturation_matrix(saturation));
+    }
+
+    /// Rotates the hue by `degrees`.
+    pub fn shift_hue(&mut self, degrees: f32) {
+        self.apply_color_matrix(hue_shift_matrix(degrees));
+    }
+
+    /// Scales the L1 coefficients relative to the L0 coefficients.
+    /// Values above `1.0` make the lighting more directional,
+    /// and `0.0` removes the directional component entirely.
+    pub fn adjust_directionality(&mut self, factor: f32) {
+        for c in &mut self.coefficients {
+            for channel in c.iter_mut() {
+                for value in &mut channel[..3] {
+                    *value *= factor;
+                }
+            }
+        }
+        self.recalculate_unk5_unk6();
+    }
+
+    /// Applies `lut` to the L0 color of each cell.
+    /// The L1 coefficients are scaled by the same ratio for each channel to preserve directionality.
+    pub fn apply_lut(&mut self, lut: &CubeLut) {
+        for c in &mut self.coefficients {
+            let rgb = [c[0][3], c[1][3], c[2][3]];
+            let new_rgb = lut.sample(rgb);
+            for (channel, (old, new)) in c.iter_mut().zip(rgb.iter().zip(new_rgb.iter())) {
+                if *old != 0.0 {
+                    let ratio = new / old;
+                    for value in &mut channel[..3] {
+                        *value *= ratio;
+                    }
+                }
+                channel[3] = *new;
+            }
+        }
+        self.recalculate_unk5_unk6();
+    }
+}
+
+impl ShanFile {
+    /// Applies [GridCoefficients::apply_color_matrix] to each TPCB.
+    pub fn apply_color_matrix(&mut self, matrix: ColorMatrix) {
+        for tpcb in &mut self.tpcbs {
+            tpcb.coefficients.apply_color_matrix(matrix);
+        }
+    }
+
+    /// Applies [GridCoefficients::adjust_exposure] to each TPCB.
+    pub fn adjust_exposure(&mut self, multiplier: f32) {
+        self.apply_color_matrix(tint_matrix([multiplier; 3]));
+    }
+
+    /// Applies [GridCoefficients::tint] to each TPCB.
+    pub fn tint(&mut self, rgb: [f32; 3]) {
+        self.apply_color_matrix(tint_matrix(rgb));
+    }
+
+    /// Applies [GridCoefficients::adjust_saturation] to each TPCB.
+    pub fn adjust_saturation(&mut self, saturation: f32) {
+        self.apply_color_matrix(saturation_matrix(saturation));
+    }
+
+    /// Applies [GridCoefficients::shift_hue] to each TPCB.
+    pub fn shift_hue(&mut self, degrees: f32) {
+        self.apply_color_matrix(hue_shift_matrix(degrees));
+    }
+
+    /// Applies [GridCoefficients::adjust_directionality] to each TPCB.
+    pub fn adjust_directionality(&mut self, factor: f32) {
+        for tpcb in &mut self.tpcbs {
+            tpcb.coefficients.adjust_directionality(factor);
+        }
+    }
+
+    /// Applies [GridCoefficients::apply_lut] to each TPCB.
+    pub fn apply_lut(&mut self, lut: &CubeLut) {
+        for tpcb in &mut self.tpcbs {
+            tpcb.coefficients.apply_lut(lut);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures;
+    use approx::relative_eq;
+
+    macro_rules! assert_almost_eq {
+        ($a:expr, $b:expr) => {
+            assert!(
+                relative_eq!($a.as_ref(), $b.as_ref(), epsilon = 0.0001),
+                "{:?} != {:?}",
+                $a,
+                $b
+            );
+        };
+    }
+
+    fn grid(coefficients: [[f32; 4]; 3]) -> GridCoefficients {
+        test_fixtures::grid([1, 1, 1], vec![coefficients])
+    }
+
+    #[test]
+    fn exposure_scales_coefficients() {
+        let mut g = grid([[0.1, 0.2, 0.3, 0.5]; 3]);
+        g.adjust_exposure(2.0);
+        assert_almost_eq!([0.2, 0.4, 0.6, 1.0], g.coefficients[0][0]);
+    }
+
+    #[test]
+    fn saturation_zero_is_gray() {
+        let mut g = grid([[0.0, 0.0, 0.0, 1.0], [0.0; 4], [0.0; 4]]);
+        g.adjust_saturation(0.0);
+        let l0 = [
+            g.coefficients[0][0][3],
+            g.coefficients[0][1][3],
+            g.coefficients[0][2][3],
+        ];
+        assert_almost_eq!([0.2126; 3], l0);
+    }
+
+    #[test]
+    fn hue_shift_red_to_green() {
+        let mut g = grid([[0.0, 0.0, 0.0, 1.0], [0.0; 4], [0.0; 4]]);
+        g.shift_hue(120.0);
+        let l0 = [
+            g.coefficients[0][0][3],
+            g.coefficients[0][1][3],
+            g.coefficients[0][2][3],
+        ];
+        assert_almost_eq!([0.0, 1.0, 0.0], l0);
+    }
+
+    #[test]
+    fn directionality_scales_l1() {
+        let mut g = grid([[0.1, 0.2, 0.3, 0.5]; 3]);
+        g.adjust_directionality(0.5);
+        assert_almost_eq!([0.05, 0.1, 0.15, 0.5], g.coefficients[0][2]);
+    }
+
+    #[test]
+    fn parse_and_apply_lut() {
+        // Swap the red and blue channels.
+        let text = "TITLE \"swap\"\n# comment\nLUT_3D_SIZE 2\n\
+            0 0 0\n0 0 1\n0 1 0\n0 1 1\n1 0 0\n1 0 1\n1 1 0\n1 1 1\n";
+        let lut = CubeLut::parse(text).unwrap();
+        assert_eq!(2, lut.size);
+        assert_almost_eq!([0.0, 0.5, 0.25], lut.sample([0.25, 0.5, 0.0]));
+
+        let mut g = grid([[0.2, 0.2, 0.2, 0.25], [0.0, 0.0, 0.0, 0.5], [0.0; 4]]);
+        g.apply_lut(&lut);
+        assert_almost_eq!([0.0, 0.0, 0.0, 0.0], g.coefficients[0][0]);
+        assert_almost_eq!([0.0, 0.0, 0.0, 0.5], g.coefficients[0][1]);
+        assert_almost_eq!([0.0, 0.0, 0.0, 0.25], g.coefficients[0][2]);
+    }
+
+    #[test]
+    fn parse_lut_unknown_keywords() {
+        let text = "LUT_3D_SIZE 2\nLUT_3D_INPUT_RANGE 0.0 2.0\nLUT_IN_VIDEO_RANGE\n\
+            0 0 0\n1 0 0\n0 1 0\n1 1 0\n0 0 1\n1 0 1\n0 1 1\n1 1 1\n";
+        let lut = CubeLut::parse(text).unwrap();
+        assert_eq!(8, lut.data.len());
+        assert_eq!([0.0; 3], lut.domain_min);
+        assert_eq!([2.0; 3], lut.domain_max);
+        assert_almost_eq!([0.5, 0.25, 0.0], lut.sample([1.0, 0.5, 0.0]));
+    }
+
+    #[test]
+    fn parse_lut_invalid_size() {
+        assert!(CubeLut::parse("LUT_3D_SIZE 3\n0 0 0\n").is_err());
+        assert!(CubeLut::parse("LUT_1D_SIZE 2\n0 0 0\n1 1 1\n").is_err());
+        // The entry count overflows.
+        assert!(CubeLut::parse("LUT_3D_SIZE 3000000\n0 0 0\n").is_err());
+    }
+}
@@ -0,0 +1,525 @@
+//! Conversions between [ShanFile] and CSV files with one row per grid cell.
+//!
+//! Each row has the TPCB index and starting frame, the cell's xyz index and world position,
+//! the TPCB's `unk5`, `unk6`, and grid bounds,
+//! the decompressed L1 and L0 floats for each color channel,
+//! and the compressed bytes for each color channel in the order they appear in the file.
+//! Importing uses the float coefficients and grid bounds,
+//! so edits to the compressed bytes and positions are ignored.
+//!
+//! Some files have more coefficients than grid cells like grids with cell counts of `[0, 0, 0]`.
+//! The extra coefficients are written after the grid cells with empty xyz and position columns.
+use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
+use std::path::Path;
+
+use crate::{sh, GridCoefficients, ShanFile, TpcbData};
+
+const CHANNELS: [&str; 3] = ["r", "g", "b"];
+const COEFFICIENTS: [&str; 4] = ["l1_x", "l1_y", "l1_z", "l0"];
+const COLUMN_COUNT: usize = 16 + 12 + 12;
+
+/// The column names for the CSV header.
+pub fn csv_header() -> Vec<String> {
+    let mut columns: Vec<String> = [
+        "tpcb",
+        "frame",
+        "x",
+        "y",
+        "z",
+        "position_x",
+        "position_y",
+        "position_z",
+        "unk5",
+        "unk6",
+        "grid_min_x",
+        "grid_min_y",
+        "grid_min_z",
+        "grid_max_x",
+        "grid_max_y",
+        "grid_max_z",
+    ]
+    .iter()
+    .map(|c| c.to_string())
+    .collect();
+
+    for channel in CHANNELS {
+        for coefficient in COEFFICIENTS {
+            columns.push(format!("{}_{}", channel, coefficient));
+        }
+    }
+    for channel in CHANNELS {
+        for i in 0..4 {
+            columns.push(format!("{}_compressed_{}", channel, i));
+        }
+    }
+    columns
+}
+
+impl ShanFile {
+    /// Writes a header and a row for every coefficient of every TPCB.
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(writer, "{}", csv_header().join(","))?;
+
+        for (i, tpcb) in self.tpcbs.iter().enumerate() {
+            let grid = &tpcb.coefficients;
+            let cell_count: usize = grid
+                .grid_cell_count_xyz
+                .iter()
+                .map(|c| *c as usize)
+                .product();
+            if grid.coefficients.len() < cell_count {
+                return Err(invalid_data(format!(
+                    "TPCB {} has fewer coefficients than grid cells",
+                    i
+                )));
+            }
+
+            for (cell, coefficients) in grid.coefficients.iter().enumerate() {
+                let mut row = vec![i.to_string(), tpcb.starting_frame.to_string()];
+                match grid.cell_xyz(cell) {
+                    Some([x, y, z]) => {
+                        row.extend([x, y, z].iter().map(|v| v.to_string()));
+                        row.extend(grid.cell_position(x, y, z).iter().map(|v| v.to_string()));
+                    }
+                    None => row.resize(8, String::new()),
+                }
+                row.push(grid.unk5.to_string());
+                row.push(grid.unk6.to_string());
+                row.extend(grid.grid_range_min_xyz.iter().map(|v| v.to_string()));
+                row.extend(grid.grid_range_max_xyz.iter().map(|v| v.to_string()));
+                row.extend(coefficients.iter().flatten().map(|v| v.to_string()));
+                row.extend(coefficients.iter().flat_map(|c| {
+                    sh::compress_coefficients(grid.unk5, grid.unk6, *c).map(|b| b.to_string())
+                }));
+
+                writeln!(writer, "{}", row.join(","))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the CSV for [ShanFile::write_csv] to `path`.
+    pub fn write_csv_file<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.write_csv(&mut writer)
+    }
+
+    /// Reads a file with the columns written by [ShanFile::write_csv].
+    /// Rows can be in any order but must contain every cell of each TPCB exactly once.
+    /// Rows with empty xyz columns are added after the grid cells in the order they appear.
+    pub fn read_csv<R: BufRead>(reader: R, name: &str) -> std::io::Result<Self> {
+        let mut lines = reader.lines();
+        let header = lines
+            .next()
+            .ok_or_else(|| invalid_data("Missing CSV header".to_string()))??;
+        let expected = csv_header();
+        let columns: Vec<_> = header.split(',').map(|c| c.trim()).collect();
+        if columns != expected {
+            return Err(invalid_data(format!(
+                "Unexpected CSV header. Expected {}",
+                expected.join(",")
+            )));
+        }
+
+        let mut rows = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            // Line numbers start from 1 and include the header.
+            rows.push(parse_row(&line, i + 2)?);
+        }
+
+        let tpcb_count = rows.iter().map(|r| r.tpcb + 1).max().unwrap_or(0);
+        let tpcbs = (0..tpcb_count)
+            .map(|i| {
+                let tpcb_rows: Vec<_> = rows.iter().filter(|r| r.tpcb == i).collect();
+                tpcb_from_rows(&tpcb_rows, i)
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            name: name.to_string(),
+            tpcbs,
+        })
+    }
+
+    /// Reads the CSV at `path` using the file name without the extension as the name.
+    pub fn read_csv_file<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let name = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Self::read_csv(BufReader::new(std::fs::File::open(path)?), &name)
+    }
+}
+
+struct CsvRow {
+    tpcb: usize,
+    frame: u32,
+    /// `None` for coefficients outside the grid.
+    xyz: Option<[usize; 3]>,
+    unk5: f32,
+    unk6: f32,
+    grid_range_min_xyz: [f32; 3],
+    grid_range_max_xyz: [f32; 3],
+    coefficients: [[f32; 4]; 3],
+}
+
+fn parse_row(line: &str, line_number: usize) -> std::io::Result<CsvRow> {
+    let values: Vec<_> = line.split(',').map(|v| v.trim()).collect();
+    if values.len() != COLUMN_COUNT {
+        return Err(invalid_data(format!(
+            "Expected {} columns but found {} on line {}",
+            COLUMN_COUNT,
+            values.len(),
+            line_number
+        )));
+    }
+
+    let parse_error = |column: usize| {
+        invalid_data(format!(
+            "Invalid value {:?} for column {} on line {}",
+            values[column],
+            csv_header()[column],
+            line_number
+        ))
+    };
+    let int = |column: usize| values[column].parse().map_err(|_| parse_error(column));
+    let float = |column: usize| {
+        values[column]
+            .parse::<f32>()
+            .map_err(|_| parse_error(column))
+    };
+
+    let mut coefficients = [[0.0; 4]; 3];
+    for (channel, c) in coefficients.iter_mut().enumerate() {
+        for (i, value) in c.iter_mut().enumerate() {
+            *value = float(16 + channel * 4 + i)?;
+        }
+    }
+
+    // Coefficients outside the grid don't have a cell index or position.
+    // The positions are only for reference since the grid bounds have their own columns.
+    let xyz = if values[2..8].iter().all(|v| v.is_empty()) {
+        None
+    } else {
+        Some([int(2)?, int(3)?, int(4)?])
+    };
+
+    Ok(CsvRow {
+        tpcb: int(0)?,
+        frame: values[1].parse().map_err(|_| parse_error(1))?,
+        xyz,
+        unk5: float(8)?,
+        unk6: float(9)?,
+        grid_range_min_xyz: [float(10)?, float(11)?, float(12)?],
+        grid_range_max_xyz: [float(13)?, float(14)?, float(15)?],
+        coefficients,
+    })
+}
+
+fn tpcb_from_rows(rows: &[&CsvRow], tpcb: usize) -> std::io::Result<TpcbData> {
+    let first = rows
+        .first()
+        .ok_or_else(|| invalid_data(format!("No rows for TPCB {}", tpcb)))?;
+
+    let (cell_rows, extra_rows): (Vec<&CsvRow>, Vec<&CsvRow>) =
+        rows.iter().partition(|r| r.xyz.is_some());
+
+    let mut counts = [0usize; 3];
+    for xyz in cell_rows.iter().filter_map(|r| r.xyz) {
+        for (count, i) in counts.iter_mut().zip(xyz) {
+            *count = (*count).max(i + 1);
+        }
+    }
+    if counts.iter().product::<usize>() != cell_rows.len() {
+        return Err(invalid_data(format!(
+            "Expected {} rows for TPCB {} with grid cell counts {:?} but found {}",
+            counts.iter().product::<usize>(),
+            tpcb,
+            counts,
+            cell_rows.len()
+        )));
+    }
+
+    let mut grid = GridCoefficients {
+        grid_cell_count_xyz: counts.map(|c| c as u32),
+        grid_range_min_xyz: first.grid_range_min_xyz,
+        grid_range_max_xyz: first.grid_range_max_xyz,
+        unk5: first.unk5,
+        unk6: first.unk6,
+        coefficients: vec![[[0.0; 4]; 3]; cell_rows.len()],
+    };
+
+    let mut found = vec![false; cell_rows.len()];
+    for row in &cell_rows {
+        let [x, y, z] = row.xyz.unwrap();
+        // The counts are calculated from the indices, so the index is always in range.
+        let cell = grid.cell_index(x, y, z).unwrap();
+        if found[cell] {
+            return Err(invalid_data(format!(
+                "Duplicate rows for cell {:?} in TPCB {}",
+                [x, y, z],
+                tpcb
+            )));
+        }
+        found[cell] = true;
+        grid.coefficients[cell] = row.coefficients;
+    }
+    grid.coefficients
+        .extend(extra_rows.iter().map(|r| r.coefficients));
+
+    if rows.iter().any(|r| r.frame != first.frame) {
+        return Err(invalid_data(format!(
+            "Inconsistent frame values for TPCB {}",
+            tpcb
+        )));
+    }
+    if rows.iter().any(|r| {
+        r.grid_range_min_xyz != first.grid_range_min_xyz
+            || r.grid_range_max_xyz != first.grid_range_max_xyz
+    }) {
+        return Err(invalid_data(format!(
+            "Inconsistent grid bounds for TPCB {}",
+            tpcb
+        )));
+    }
+
+    Ok(TpcbData {
+        starting_frame: first.frame,
+        coefficients: grid,
+    })
+}
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{grid, shan_file};
+
+    fn file() -> ShanFile {
+        let grid = |offset: f32| {
+            let coefficients = (0..6)
+                .map(|i| {
+                    let v = i as f32 * 0.1 + offset;
+                    [[v, -v, 0.125, 0.5], [0.0, 0.3, v, 0.25], [0.1, 0.2, 0.3, v]]
+                })
+                .collect();
+            GridCoefficients {
+                grid_range_min_xyz: [-1.0, 0.5, 2.0],
+                grid_range_max_xyz: [1.0, 1.5, 2.0],
+                unk5: -1.0247978,
+                unk6: 0.0313374,
+                ..grid([2, 3, 1], coefficients)
+            }
+        };
+        shan_file(vec![(0, grid(0.0)), (20, grid(1.0))])
+    }
+
+    fn write(file: &ShanFile) -> String {
+        let mut bytes = Vec::new();
+        file.write_csv(&mut bytes).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    fn read(csv: &str) -> std::io::Result<ShanFile> {
+        ShanFile::read_csv(csv.as_bytes(), "chara")
+    }
+
+    #[test]
+    fn header() {
+        let header = csv_header();
+        assert_eq!(COLUMN_COUNT, header.len());
+        assert_eq!("position_x", header[5]);
+        assert_eq!("grid_min_x", header[10]);
+        assert_eq!("grid_max_z", header[15]);
+        assert_eq!("r_l1_x", header[16]);
+        assert_eq!("b_l0", header[27]);
+        assert_eq!("r_compressed_0", header[28]);
+        assert_eq!("b_compressed_3", header[39]);
+    }
+
+    #[test]
+    fn write_csv_rows() {
+        let csv = write(&file());
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(1 + 12, lines.len());
+
+        // The second cell is at x=1.
+        let values: Vec<_> = lines[2].split(',').collect();
+        assert_eq!(
+            vec![
+                "0",
+                "0",
+                "1",
+                "0",
+                "0",
+                "1",
+                "0.5",
+                "2",
+                "-1.0247978",
+                "0.0313374",
+                "-1",
+                "0.5",
+                "2",
+                "1",
+                "1.5",
+                "2"
+            ],
+            values[..16]
+        );
+        assert_eq!(vec!["0.1", "-0.1", "0.125", "0.5"], values[16..20]);
+
+        // The last row is the last cell of the second TPCB.
+        let values: Vec<_> = lines[12].split(',').collect();
+        assert_eq!(vec!["1", "20", "1", "2", "0", "1", "1.5", "2"], values[..8]);
+    }
+
+    #[test]
+    fn write_csv_compressed_bytes() {
+        let file = file();
+        let csv = write(&file);
+        let values: Vec<u8> = csv.lines().nth(1).unwrap().split(',').collect::<Vec<_>>()[28..]
+            .iter()
+            .map(|v| v.parse().unwrap())
+            .collect();
+
+        let grid = &file.tpcbs[0].coefficients;
+        let expected: Vec<_> = grid.coefficients[0]
+            .iter()
+            .flat_map(|c| sh::compress_coefficients(grid.unk5, grid.unk6, *c))
+            .collect();
+        assert_eq!(expected, values);
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let file = file();
+        assert_eq!(file, read(&write(&file)).unwrap());
+    }
+
+    #[test]
+    fn csv_round_trip_grid_bounds() {
+        // Bounds from a real file aren't exactly min + (count - 1) * spacing.
+        let file = shan_file(vec![(
+            0,
+            GridCoefficients {
+                grid_range_min_xyz: [-563.37305, -98.03044, 0.0],
+                grid_range_max_xyz: [65.51749, 127.32863, 0.0],
+                ..grid([21, 10, 1], vec![[[0.5; 4]; 3]; 210])
+            },
+        )]);
+
+        let result = read(&write(&file)).unwrap();
+        let bounds = |f: &ShanFile| {
+            let grid = &f.tpcbs[0].coefficients;
+            [grid.grid_range_min_xyz, grid.grid_range_max_xyz].map(|b| b.map(f32::to_bits))
+        };
+        assert_eq!(bounds(&file), bounds(&result));
+        assert_eq!(file, result);
+    }
+
+    #[test]
+    fn read_csv_inconsistent_grid_bounds() {
+        let csv = write(&file());
+        let mut lines: Vec<_> = csv.lines().map(|l| l.to_string()).collect();
+        lines[2] = lines[2].replacen(",-1,0.5,2,1,1.5,2,", ",-1,0.5,2,1,1.5,3,", 1);
+        let message = read(&lines.join("\n")).unwrap_err().to_string();
+        assert_eq!("Inconsistent grid bounds for TPCB 0", message);
+    }
+
+    #[test]
+    fn read_csv_shuffled_rows() {
+        let file = file();
+        let csv = write(&file);
+        let mut lines: Vec<_> = csv.lines().collect();
+        lines[1..].reverse();
+        assert_eq!(file, read(&lines.join("\n")).unwrap());
+    }
+
+    #[test]
+    fn csv_empty_grid_cell_counts() {
+        // xeno_gaur has coefficients but no grid cells.
+        let mut file = file();
+        file.tpcbs.truncate(1);
+        let grid = &mut file.tpcbs[0].coefficients;
+        grid.grid_cell_count_xyz = [0, 0, 0];
+        grid.grid_range_min_xyz = [0.0; 3];
+        grid.grid_range_max_xyz = [0.0; 3];
+
+        let csv = write(&file);
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(1 + 6, lines.len());
+        assert!(lines[1].starts_with("0,0,,,,,,,-1.0247978,0.0313374,0,0,0,0,0,0,0,-0,0.125,0.5,"));
+        assert_eq!(file, read(&csv).unwrap());
+    }
+
+    #[test]
+    fn csv_extra_coefficients() {
+        let mut file = file();
+        file.tpcbs[0].coefficients.coefficients.push([[0.75; 4]; 3]);
+
+        let csv = write(&file);
+        assert_eq!(1 + 13, csv.lines().count());
+        assert!(csv.lines().nth(7).unwrap().starts_with("0,0,,,,,,,"));
+        assert_eq!(file, read(&csv).unwrap());
+    }
+
+    #[test]
+    fn write_csv_missing_coefficients() {
+        let mut file = file();
+        file.tpcbs[1].coefficients.coefficients.pop();
+        let mut bytes = Vec::new();
+        let result = file.write_csv(&mut bytes);
+        assert_eq!(ErrorKind::InvalidData, result.unwrap_err().kind());
+    }
+
+    #[test]
+    fn read_csv_invalid_header() {
+        let result = read("tpcb,frame\n");
+        assert_eq!(ErrorKind::InvalidData, result.unwrap_err().kind());
+    }
+
+    #[test]
+    fn read_csv_empty() {
+        assert!(read("").is_err());
+
+        let file = read(&csv_header().join(",")).unwrap();
+        assert!(file.tpcbs.is_empty());
+    }
+
+    #[test]
+    fn read_csv_missing_cell() {
+        let csv = write(&file());
+        let lines: Vec<_> = csv.lines().filter(|l| !l.starts_with("0,0,1,1,")).collect();
+        assert!(read(&lines.join("\n")).is_err());
+    }
+
+    #[test]
+    fn read_csv_duplicate_cell() {
+        let csv = write(&file());
+        let mut lines: Vec<_> = csv.lines().collect();
+        // Replace the last cell of the first TPCB with a copy of the first cell.
+        lines[6] = lines[1];
+        assert!(read(&lines.join("\n")).is_err());
+    }
+
+    #[test]
+    fn read_csv_invalid_value() {
+        let csv = write(&file()).replacen("0.125", "abc", 1);
+        let message = read(&csv).unwrap_err().to_string();
+        assert_eq!("Invalid value \"abc\" for column r_l1_z on line 2", message);
+    }
+
+    #[test]
+    fn read_csv_column_count() {
+        let csv = format!("{}\n0,0,0", csv_header().join(","));
+        assert!(read(&csv).is_err());
+    }
+}
@@ -0,0 +1,661 @@
+//! Semantic differences between two files for reviewing changes to lighting data.
+//!
+//! TPCBs are matched by starting frame. Unmatched TPCBs are paired in order as moved keyframes,
+//! and any remaining TPCBs are added or removed.
+//! Coefficients are only compared for matched TPCBs with the same grid cell counts.
+//! [ShanFile] doesn't store header values like `unk1` or `unk1_2`,
+//! so use [Shan::diff] to also compare the header values.
+use std::fmt::Display;
+
+use crate::image::HdrImage;
+use crate::shan::{Shan, TpcbHeader};
+use crate::{ConvertError, GridCoefficients, ShanFile};
+
+/// A change to the keyframes for the TPCBs in a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyframeChange {
+    /// The new file has a TPCB at `new_index` with no match in the old file.
+    Added { new_index: usize, frame: u32 },
+    /// The old file has a TPCB at `old_index` with no match in the new file.
+    Removed { old_index: usize, frame: u32 },
+    /// The TPCB at `old_index` starts at a different frame in the new file.
+    Moved {
+        old_index: usize,
+        new_index: usize,
+        old_frame: u32,
+        new_frame: u32,
+    },
+}
+
+impl Display for KeyframeChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyframeChange::Added { new_index, frame } => {
+                write!(f, "Added TPCB {} at frame {}", new_index, frame)
+            }
+            KeyframeChange::Removed { old_index, frame } => {
+                write!(f, "Removed TPCB {} at frame {}", old_index, frame)
+            }
+            KeyframeChange::Moved {
+                old_index,
+                new_index,
+                old_frame,
+                new_frame,
+            } => write!(
+                f,
+                "Moved TPCB {} from frame {} to TPCB {} at frame {}",
+                old_index, old_frame, new_index, new_frame
+            ),
+        }
+    }
+}
+
+/// A change to the header values for a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderChange {
+    Unk1 { old: u32, new: u32 },
+    Unk3 { old: u32, new: u32 },
+}
+
+impl Display for HeaderChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeaderChange::Unk1 { old, new } => write!(f, "unk1: {} -> {}", old, new),
+            HeaderChange::Unk3 { old, new } => write!(f, "unk3: {} -> {}", old, new),
+        }
+    }
+}
+
+/// A change to the grid or header values for a TPCB.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldChange {
+    GridCellCount {
+        old: [u32; 3],
+        new: [u32; 3],
+    },
+    GridRangeMin {
+        old: [f32; 3],
+        new: [f32; 3],
+    },
+    GridRangeMax {
+        old: [f32; 3],
+        new: [f32; 3],
+    },
+    Unk5 {
+        old: f32,
+        new: f32,
+    },
+    Unk6 {
+        old: f32,
+        new: f32,
+    },
+    /// The number of coefficients differs, which may not match the grid cell count.
+    CoefficientCount {
+        old: usize,
+        new: usize,
+    },
+    /// Only compared by [Shan::diff].
+    Unk1_1 {
+        old: u16,
+        new: u16,
+    },
+    /// Only compared by [Shan::diff].
+    Unk1_2 {
+        old: u16,
+        new: u16,
+    },
+    /// Only compared by [Shan::diff].
+    Unk4 {
+        old: u32,
+        new: u32,
+    },
+}
+
+impl Display for FieldChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldChange::GridCellCount { old, new } => {
+                write!(f, "grid_cell_count_xyz: {:?} -> {:?}", old, new)
+            }
+            FieldChange::GridRangeMin { old, new } => {
+                write!(f, "grid_range_min_xyz: {:?} -> {:?}", old, new)
+            }
+            FieldChange::GridRangeMax { old, new } => {
+                write!(f, "grid_range_max_xyz: {:?} -> {:?}", old, new)
+            }
+            FieldChange::Unk5 { old, new } => write!(f, "unk5: {} -> {}", old, new),
+            FieldChange::Unk6 { old, new } => write!(f, "unk6: {} -> {}", old, new),
+            FieldChange::CoefficientCount { old, new } => {
+                write!(f, "coefficient count: {} -> {}", old, new)
+            }
+            FieldChange::Unk1_1 { old, new } => write!(f, "unk1_1: {} -> {}", old, new),
+            FieldChange::Unk1_2 { old, new } => write!(f, "unk1_2: {} -> {}", old, new),
+            FieldChange::Unk4 { old, new } => write!(f, "unk4: {} -> {}", old, new),
+        }
+    }
+}
+
+/// The differences between a pair of matched TPCBs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TpcbDiff {
+    pub old_index: usize,
+    pub new_index: usize,
+    /// The starting frame in the new file.
+    pub starting_frame: u32,
+    pub fields: Vec<FieldChange>,
+    /// The grid cell counts used for [TpcbDiff::heat_map].
+    pub grid_cell_count_xyz: [u32; 3],
+    /// The largest absolute difference of any float coefficient for each cell
+    /// or empty if the grid cell counts differ.
+    /// Changes to or from non-finite values have a difference of infinity.
+    pub cell_differences: Vec<f32>,
+    /// The indices of cells with a difference larger than the tolerance.
+    pub changed_cells: Vec<usize>,
+}
+
+impl TpcbDiff {
+    /// Returns `true` if there are no field changes and no changed cells.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.changed_cells.is_empty()
+    }
+
+    /// The largest value in [TpcbDiff::cell_differences] or `0.0` if there are no cells.
+    pub fn max_difference(&self) -> f32 {
+        self.cell_differences.iter().copied().fold(0.0, f32::max)
+    }
+
+    /// Creates an image with one pixel per cell showing the changed cells in red
+    /// scaled by the largest finite difference.
+    /// Cells with an infinite difference are white.
+    /// The Z slices are placed side by side from left to right,
+    /// and each slice has Y increasing from bottom to top.
+    /// Returns `None` if the coefficients were not compared
+    /// or the number of coefficients doesn't match the grid cell counts.
+    pub fn heat_map(&self) -> Option<HdrImage> {
+        let [nx, ny, nz] = self.grid_cell_count_xyz;
+        let cell_count = nx as usize * ny as usize * nz as usize;
+        if self.cell_differences.is_empty() || self.cell_differences.len() != cell_count {
+            return None;
+        }
+
+        let max_difference = self
+            .cell_differences
+            .iter()
+            .copied()
+            .filter(|d| d.is_finite())
+            .fold(0.0, f32::max);
+        let mut image = HdrImage::new(nx * nz, ny, [0.0; 3]);
+        for index in &self.changed_cells {
+            let x = index % nx as usize;
+            let y = (index / nx as usize) % ny as usize;
+            let z = index / (nx as usize * ny as usize);
+            let column = z * nx as usize + x;
+            let row = ny as usize - 1 - y;
+            let difference = self.cell_differences[*index];
+            image.data[row * image.width as usize + column] = if difference.is_infinite() {
+                [1.0; 3]
+            } else if max_difference > 0.0 {
+                [difference / max_difference, 0.0, 0.0]
+            } else {
+                [0.0; 3]
+            };
+        }
+        Some(image)
+    }
+}
+
+/// The differences between two files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShanDiff {
+    /// The old and new names if the names differ.
+    pub name: Option<(String, String)>,
+    /// Only compared by [Shan::diff].
+    pub header: Vec<HeaderChange>,
+    pub timeline: Vec<KeyframeChange>,
+    /// The differences for each pair of matched TPCBs including unchanged TPCBs.
+    pub tpcbs: Vec<TpcbDiff>,
+}
+
+impl ShanDiff {
+    /// Returns `true` if the files have no differences above the tolerance.
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.header.is_empty()
+            && self.timeline.is_empty()
+            && self.tpcbs.iter().all(|t| t.is_empty())
+    }
+}
+
+impl ShanFile {
+    /// Compares `self` as the old file to `new`.
+    /// Cells are only considered changed if a float coefficient differs by more than `tolerance`.
+    pub fn diff(&self, new: &ShanFile, tolerance: f32) -> ShanDiff {
+        let name = (self.name != new.name).then(|| (self.name.clone(), new.name.clone()));
+
+        // Match TPCBs with the same starting frame.
+        let mut pairs = Vec::new();
+        let mut unmatched_old = Vec::new();
+        let mut matched_new = vec![false; new.tpcbs.len()];
+        for (old_index, old) in self.tpcbs.iter().enumerate() {
+            match new
+                .tpcbs
+                .iter()
+                .enumerate()
+                .position(|(i, t)| !matched_new[i] && t.starting_frame == old.starting_frame)
+            {
+                Some(new_index) => {
+                    matched_new[new_index] = true;
+                    pairs.push((old_index, new_index));
+                }
+                None => unmatched_old.push(old_index),
+            }
+        }
+        let unmatched_new: Vec<_> = (0..new.tpcbs.len()).filter(|i| !matched_new[*i]).collect();
+
+        // Pair the remaining TPCBs in order as moved keyframes.
+        let mut timeline = Vec::new();
+        for (old_index, new_index) in unmatched_old.iter().zip(&unmatched_new) {
+            timeline.push(KeyframeChange::Moved {
+                old_index: *old_index,
+                new_index: *new_index,
+                old_frame: self.tpcbs[*old_index].starting_frame,
+                new_frame: new.tpcbs[*new_index].starting_frame,
+            });
+            pairs.push((*old_index, *new_index));
+        }
+        for old_index in unmatched_old.iter().skip(unmatched_new.len()) {
+            timeline.push(KeyframeChange::Removed {
+                old_index: *old_index,
+                frame: self.tpcbs[*old_index].starting_frame,
+            });
+        }
+        for new_index in unmatched_new.iter().skip(unmatched_old.len()) {
+            timeline.push(KeyframeChange::Added {
+                new_index: *new_index,
+                frame: new.tpcbs[*new_index].starting_frame,
+            });
+        }
+
+        pairs.sort_by_key(|(_, new_index)| *new_index);
+        let tpcbs = pairs
+            .into_iter()
+            .map(|(old_index, new_index)| {
+                let old = &self.tpcbs[old_index].coefficients;
+                let new_tpcb = &new.tpcbs[new_index];
+                let (cell_differences, changed_cells) =
+                    cell_differences(old, &new_tpcb.coefficients, tolerance);
+                TpcbDiff {
+                    old_index,
+                    new_index,
+                    starting_frame: new_tpcb.starting_frame,
+                    fields: field_changes(old, &new_tpcb.coefficients),
+                    grid_cell_count_xyz: new_tpcb.coefficients.grid_cell_count_xyz,
+                    cell_differences,
+                    changed_cells,
+                }
+            })
+            .collect();
+
+        ShanDiff {
+            name,
+            header: Vec::new(),
+            timeline,
+            tpcbs,
+        }
+    }
+}
+
+impl Shan {
+    /// Compares `self` as the old file to `new` like [ShanFile::diff]
+    /// including the header values not stored in [ShanFile].
+    pub fn diff(&self, new: &Shan, tolerance: f32) -> Result<ShanDiff, ConvertError> {
+        let mut diff = ShanFile::try_from(self)?.diff(&ShanFile::try_from(new)?, tolerance);
+        if self.unk1 != new.unk1 {
+            diff.header.push(HeaderChange::Unk1 {
+                old: self.unk1,
+                new: new.unk1,
+            });
+        }
+        if self.unk3 != new.unk3 {
+            diff.header.push(HeaderChange::Unk3 {
+                old: self.unk3,
+                new: new.unk3,
+            });
+        }
+
+        for tpcb in &mut diff.tpcbs {
+            let old_tpcb = self.tpcbs[tpcb.old_index].as_ref();
+            let new_tpcb = new.tpcbs[tpcb.new_index].as_ref();
+            if let (Some(old_tpcb), Some(new_tpcb)) = (old_tpcb, new_tpcb) {
+                tpcb.fields.extend(header_changes(
+                    &old_tpcb.inner.header,
+                    &new_tpcb.inner.header,
+                ));
+            }
+        }
+        Ok(diff)
+    }
+}
+
+fn header_changes(old: &TpcbHeader, new: &TpcbHeader) -> Vec<FieldChange> {
+    let mut fields = Vec::new();
+    if old.unk1_1 != new.unk1_1 {
+        fields.push(FieldChange::Unk1_1 {
+            old: old.unk1_1,
+            new: new.unk1_1,
+        });
+    }
+    if old.unk1_2 != new.unk1_2 {
+        fields.push(FieldChange::Unk1_2 {
+            old: old.unk1_2,
+            new: new.unk1_2,
+        });
+    }
+    if old.unk4 != new.unk4 {
+        fields.push(FieldChange::Unk4 {
+            old: old.unk4,
+            new: new.unk4,
+        });
+    }
+    fields
+}
+
+fn field_changes(old: &GridCoefficients, new: &GridCoefficients) -> Vec<FieldChange> {
+    let mut fields = Vec::new();
+    if old.grid_cell_count_xyz != new.grid_cell_count_xyz {
+        fields.push(FieldChange::GridCellCount {
+            old: old.grid_cell_count_xyz,
+            new: new.grid_cell_count_xyz,
+        });
+    }
+    if old.grid_range_min_xyz != new.grid_range_min_xyz {
+        fields.push(FieldChange::GridRangeMin {
+            old: old.grid_range_min_xyz,
+            new: new.grid_range_min_xyz,
+        });
+    }
+    if old.grid_range_max_xyz != new.grid_range_max_xyz {
+        fields.push(FieldChange::GridRangeMax {
+            old: old.grid_range_max_xyz,
+            new: new.grid_range_max_xyz,
+        });
+    }
+    // Compare bits so NaN values aren't reported as changed.
+    if old.unk5.to_bits() != new.unk5.to_bits() {
+        fields.push(FieldChange::Unk5 {
+            old: old.unk5,
+            new: new.unk5,
+        });
+    }
+    if old.unk6.to_bits() != new.unk6.to_bits() {
+        fields.push(FieldChange::Unk6 {
+            old: old.unk6,
+            new: new.unk6,
+        });
+    }
+    if old.coefficients.len() != new.coefficients.len() {
+        fields.push(FieldChange::CoefficientCount {
+            old: old.coefficients.len(),
+            new: new.coefficients.len(),
+        });
+    }
+    fields
+}
+
+fn cell_differences(
+    old: &GridCoefficients,
+    new: &GridCoefficients,
+    tolerance: f32,
+) -> (Vec<f32>, Vec<usize>) {
+    if old.grid_cell_count_xyz != new.grid_cell_count_xyz {
+        return (Vec::new(), Vec::new());
+    }
+
+    // Cells missing from either file are compared to zero.
+    let cell_count = old.coefficients.len().max(new.coefficients.len());
+    let differences: Vec<_> = (0..cell_count)
+        .map(|i| {
+            let a = old.coefficients.get(i).copied().unwrap_or_default();
+            let b = new.coefficients.get(i).copied().unwrap_or_default();
+            a.iter()
+                .flatten()
+                .zip(b.iter().flatten())
+                .map(|(a, b)| difference(*a, *b))
+                .fold(0.0, f32::max)
+        })
+        .collect();
+    let changed = differences
+        .iter()
+        .enumerate()
+        .filter(|(_, d)| **d > tolerance)
+        .map(|(i, _)| i)
+        .collect();
+    (differences, changed)
+}
+
+// NaN would be ignored when finding the maximum, so treat changes to or from NaN as infinite.
+fn difference(a: f32, b: f32) -> f32 {
+    if a.to_bits() == b.to_bits() {
+        0.0
+    } else {
+        let difference = (a - b).abs();
+        if difference.is_nan() {
+            f32::INFINITY
+        } else {
+            difference
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{grid, shan_file};
+    use crate::GridIndexMode;
+    use ssbh_lib::Ptr32;
+
+    fn tpcb(starting_frame: u32, values: &[f32]) -> (u32, GridCoefficients) {
+        let coefficients = values.iter().map(|v| [[*v; 4]; 3]).collect();
+        (
+            starting_frame,
+            grid([2, 1, values.len() as u32 / 2], coefficients),
+        )
+    }
+
+    #[test]
+    fn diff_identical() {
+        let file = shan_file(vec![tpcb(0, &[0.0, 1.0]), tpcb(10, &[0.5, 0.5])]);
+        let diff = file.diff(&file, 0.0);
+        assert!(diff.is_empty());
+        assert_eq!(2, diff.tpcbs.len());
+        let image = diff.tpcbs[0].heat_map().unwrap();
+        assert_eq!(vec![[0.0; 3]; 2], image.data);
+    }
+
+    #[test]
+    fn diff_name() {
+        let old = shan_file(Vec::new());
+        let new = ShanFile {
+            name: "stage".to_string(),
+            tpcbs: Vec::new(),
+        };
+        let diff = old.diff(&new, 0.0);
+        assert_eq!(Some(("chara".to_string(), "stage".to_string())), diff.name);
+        assert!(!diff.is_empty());
+    }
+
+    #[test]
+    fn diff_timeline() {
+        let old = shan_file(vec![
+            tpcb(0, &[0.0, 0.0]),
+            tpcb(10, &[0.0, 0.0]),
+            tpcb(20, &[0.0, 0.0]),
+        ]);
+        let new = shan_file(vec![tpcb(0, &[0.0, 0.0]), tpcb(15, &[0.0, 0.0])]);
+        let diff = old.diff(&new, 0.0);
+        assert_eq!(
+            vec![
+                KeyframeChange::Moved {
+                    old_index: 1,
+                    new_index: 1,
+                    old_frame: 10,
+                    new_frame: 15
+                },
+                KeyframeChange::Removed {
+                    old_index: 2,
+                    frame: 20
+                }
+            ],
+            diff.timeline
+        );
+        assert_eq!(2, diff.tpcbs.len());
+
+        let diff = new.diff(&old, 0.0);
+        assert_eq!(
+            KeyframeChange::Added {
+                new_index: 2,
+                frame: 20
+            },
+            diff.timeline[1]
+        );
+    }
+
+    #[test]
+    fn diff_fields() {
+        let old = shan_file(vec![tpcb(0, &[0.0, 0.0])]);
+        let mut new = old.clone();
+        new.tpcbs[0].coefficients.grid_range_max_xyz = [2.0; 3];
+        new.tpcbs[0].coefficients.unk6 = 0.5;
+        new.tpcbs[0].coefficients.grid_cell_count_xyz = [1, 1, 1];
+        new.tpcbs[0].coefficients.coefficients.pop();
+
+        let diff = old.diff(&new, 0.0);
+        assert_eq!(
+            vec![
+                FieldChange::GridCellCount {
+                    old: [2, 1, 1],
+                    new: [1, 1, 1]
+                },
+                FieldChange::GridRangeMax {
+                    old: [1.0, 0.0, 0.0],
+                    new: [2.0; 3]
+                },
+                FieldChange::Unk6 { old: 0.0, new: 0.5 },
+                FieldChange::CoefficientCount { old: 2, new: 1 },
+            ],
+            diff.tpcbs[0].fields
+        );
+        assert!(diff.tpcbs[0].cell_differences.is_empty());
+        assert_eq!(None, diff.tpcbs[0].heat_map());
+    }
+
+    #[test]
+    fn diff_cells_tolerance() {
+        let old = shan_file(vec![tpcb(0, &[0.0, 0.0, 1.0, 1.0])]);
+        let new = shan_file(vec![tpcb(0, &[0.001, 0.5, 1.0, 0.0])]);
+
+        let diff = old.diff(&new, 0.01);
+        let tpcb = &diff.tpcbs[0];
+        assert!(tpcb.fields.is_empty());
+        assert_eq!(vec![1, 3], tpcb.changed_cells);
+        assert_eq!(1.0, tpcb.max_difference());
+
+        let diff = old.diff(&new, 0.0);
+        assert_eq!(vec![0, 1, 3], diff.tpcbs[0].changed_cells);
+    }
+
+    #[test]
+    fn diff_heat_map() {
+        // Cells are [0, 1] at z=0 and [2, 3] at z=1.
+        let old = shan_file(vec![tpcb(0, &[0.0, 0.0, 1.0, 1.0])]);
+        let new = shan_file(vec![tpcb(0, &[0.0, 0.5, 1.0, 0.0])]);
+        let image = old.diff(&new, 0.0).tpcbs[0].heat_map().unwrap();
+        assert_eq!((4, 1), (image.width, image.height));
+        assert_eq!(
+            vec![[0.0; 3], [0.5, 0.0, 0.0], [0.0; 3], [1.0, 0.0, 0.0]],
+            image.data
+        );
+    }
+
+    #[test]
+    fn diff_heat_map_mismatched_cell_count() {
+        // The cell counts match between files but not the number of coefficients.
+        for count in [[0, 0, 0], [2, 1, 1]] {
+            let mut old = shan_file(vec![tpcb(0, &[0.0, 0.0, 1.0, 1.0])]);
+            let mut new = shan_file(vec![tpcb(0, &[0.0, 0.5, 1.0, 0.0])]);
+            old.tpcbs[0].coefficients.grid_cell_count_xyz = count;
+            new.tpcbs[0].coefficients.grid_cell_count_xyz = count;
+
+            let diff = old.diff(&new, 0.0);
+            assert_eq!(vec![1, 3], diff.tpcbs[0].changed_cells);
+            assert_eq!(None, diff.tpcbs[0].heat_map());
+        }
+    }
+
+    #[test]
+    fn diff_heat_map_negative_tolerance() {
+        let file = shan_file(vec![tpcb(0, &[0.0, 1.0])]);
+        let image = file.diff(&file, -1.0).tpcbs[0].heat_map().unwrap();
+        assert_eq!(vec![[0.0; 3]; 2], image.data);
+    }
+
+    #[test]
+    fn diff_nan_cells() {
+        let old = shan_file(vec![tpcb(0, &[0.0, 1.0, f32::NAN, 2.0])]);
+        let new = shan_file(vec![tpcb(0, &[f32::NAN, 1.0, f32::NAN, 2.5])]);
+
+        let diff = old.diff(&new, 0.0);
+        let tpcb = &diff.tpcbs[0];
+        assert_eq!(vec![0, 3], tpcb.changed_cells);
+        assert_eq!(f32::INFINITY, tpcb.cell_differences[0]);
+        assert_eq!(0.0, tpcb.cell_differences[2]);
+
+        let image = tpcb.heat_map().unwrap();
+        assert_eq!(
+            vec![[1.0; 3], [0.0; 3], [0.0; 3], [1.0, 0.0, 0.0]],
+            image.data
+        );
+    }
+
+    #[test]
+    fn diff_nan_identical() {
+        let mut file = shan_file(vec![tpcb(0, &[f32::NAN, 1.0])]);
+        file.tpcbs[0].coefficients.unk5 = f32::NAN;
+        file.tpcbs[0].coefficients.unk6 = f32::NAN;
+        assert!(file.diff(&file, 0.0).is_empty());
+    }
+
+    #[test]
+    fn diff_shan_header() {
+        let old = shan_file(vec![tpcb(0, &[0.0, 1.0])])
+            .to_shan(GridIndexMode::Identity)
+            .unwrap();
+        let mut new = old.clone();
+        new.unk1 += 1;
+        new.unk3 = 1;
+        let mut tpcb = old.tpcbs[0].as_ref().unwrap().clone();
+        tpcb.inner.header.unk1_2 = 3;
+        tpcb.inner.header.unk4 = 0;
+        new.tpcbs[0] = Ptr32::new(tpcb);
+
+        let diff = old.diff(&new, 0.0).unwrap();
+        assert_eq!(
+            vec![
+                HeaderChange::Unk1 {
+                    old: old.unk1,
+                    new: old.unk1 + 1
+                },
+                HeaderChange::Unk3 { old: 0, new: 1 }
+            ],
+            diff.header
+        );
+        assert_eq!(
+            vec![
+                FieldChange::Unk1_2 { old: 35, new: 3 },
+                FieldChange::Unk4 { old: 12, new: 0 }
+            ],
+            diff.tpcbs[0].fields
+        );
+        assert!(old.diff(&old, 0.0).unwrap().is_empty());
+    }
+}
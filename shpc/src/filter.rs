@@ -0,0 +1,284 @@
+//! Spatial filters for smoothing and denoising probe grids.
+//! Each filter uses a radius in cells for each axis, so a radius of `[2, 2, 0]`
+//! only filters along X and Y. Windows are clamped to the grid bounds.
+use crate::color::luminance;
+use crate::GridCoefficients;
+
+type Cell = [[f32; 4]; 3];
+
+impl GridCoefficients {
+    /// Applies a separable Gaussian blur with a standard deviation of half the radius for each axis.
+    ///
+    /// The `unk5` and `unk6` values are recalculated to fit the filtered coefficients.
+    pub fn gaussian_blur(&self, radius: [usize; 3]) -> Self {
+        let mut result = self.clone();
+        for (axis, r) in radius.iter().enumerate() {
+            if *r == 0 {
+                continue;
+            }
+
+            let mut axis_radius = [0; 3];
+            axis_radius[axis] = *r;
+            let sigma = *r as f32 / 2.0;
+            result = result.filter_cells(axis_radius, |_, window| {
+                weighted_average(
+                    window
+                        .iter()
+                        .map(|(offset, c)| (gaussian(offset[axis] as f32, sigma), *c)),
+                )
+            });
+        }
+
+        result.recalculate_unk5_unk6();
+        result
+    }
+
+    /// Applies a bilateral filter that only averages cells with similar L0 [luminance].
+    /// The spatial weights use a standard deviation of half the radius for each axis.
+    /// Smaller values for `luminance_sigma` better preserve sharp changes in lighting.
+    ///
+    /// The `unk5` and `unk6` values are recalculated to fit the filtered coefficients.
+    pub fn bilateral_filter(&self, radius: [usize; 3], luminance_sigma: f32) -> Self {
+        let sigma = radius.map(|r| r as f32 / 2.0);
+        let mut result = self.filter_cells(radius, |center, window| {
+            let center_luminance = l0_luminance(center);
+            weighted_average(window.iter().map(|(offset, c)| {
+                let spatial: f32 = (0..3)
+                    .map(|i| gaussian(offset[i] as f32, sigma[i]))
+                    .product();
+                let range = gaussian(l0_luminance(c) - center_luminance, luminance_sigma);
+                (spatial * range, *c)
+            }))
+        });
+
+        result.recalculate_unk5_unk6();
+        result
+    }
+
+    /// Replaces each coefficient with the median of the values in the window around each cell.
+    /// This removes isolated outliers while preserving sharp changes in lighting.
+    ///
+    /// The `unk5` and `unk6` values are recalculated to fit the filtered coefficients.
+    pub fn median_filter(&self, radius: [usize; 3]) -> Self {
+        let mut result = self.filter_cells(radius, |_, window| {
+            let mut median = [[0.0; 4]; 3];
+            for (channel, c) in median.iter_mut().enumerate() {
+                for (i, value) in c.iter_mut().enumerate() {
+                    let mut values: Vec<_> = window.iter().map(|(_, w)| w[channel][i]).collect();
+                    values.sort_by(f32::total_cmp);
+
+                    let mid = values.len() / 2;
+                    *value = if values.len() % 2 == 0 {
+                        (values[mid - 1] + values[mid]) / 2.0
+                    } else {
+                        values[mid]
+                    };
+                }
+            }
+            median
+        });
+
+        result.recalculate_unk5_unk6();
+        result
+    }
+
+    // Replace each cell with the result of f for the cell and its window of neighbors.
+    // Each neighbor has the xyz offset relative to the center cell.
+    fn filter_cells<F>(&self, radius: [usize; 3], f: F) -> Self
+    where
+        F: Fn(&Cell, &[([isize; 3], &Cell)]) -> Cell,
+    {
+        let mut result = self.clone();
+        let r = radius.map(|r| r as isize);
+
+        let [nx, ny, nz] = self.grid_cell_count_xyz.map(|c| c as usize);
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx {
+                    let index = match self.cell_index(x, y, z) {
+                        Some(index) if index < self.coefficients.len() => index,
+                        _ => continue,
+                    };
+
+                    let mut window = Vec::new();
+                    for dz in -r[2]..=r[2] {
+                        for dy in -r[1]..=r[1] {
+                            for dx in -r[0]..=r[0] {
+                                let neighbor = [x as isize + dx, y as isize + dy, z as isize + dz];
+                                if neighbor.iter().any(|i| *i < 0) {
+                                    continue;
+                                }
+                                let [nx, ny, nz] = neighbor.map(|i| i as usize);
+                                if let Some(c) = self
+                                    .cell_index(nx, ny, nz)
+                                    .and_then(|i| self.coefficients.get(i))
+                                {
+                                    window.push(([dx, dy, dz], c));
+                                }
+                            }
+                        }
+                    }
+
+                    result.coefficients[index] = f(&self.coefficients[index], &window);
+                }
+            }
+        }
+        result
+    }
+}
+
+fn l0_luminance(cell: &Cell) -> f32 {
+    luminance(cell.map(|c| c[3]))
+}
+
+fn gaussian(distance: f32, sigma: f32) -> f32 {
+    if sigma > 0.0 {
+        (-distance * distance / (2.0 * sigma * sigma)).exp()
+    } else if distance == 0.0 {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn weighted_average<'a, I: Iterator<Item = (f32, &'a Cell)>>(values: I) -> Cell {
+    let mut result = [[0.0; 4]; 3];
+    let mut weight_sum = 0.0;
+    for (weight, cell) in values {
+        for (r, c) in result.iter_mut().zip(cell) {
+            for (r, c) in r.iter_mut().zip(c) {
+                *r += c * weight;
+            }
+        }
+        weight_sum += weight;
+    }
+
+    if weight_sum > 0.0 {
+        for r in result.iter_mut().flatten() {
+            *r /= weight_sum;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures;
+
+    fn grid(counts: [u32; 3], values: &[f32]) -> GridCoefficients {
+        test_fixtures::grid(counts, values.iter().map(|v| [[*v; 4]; 3]).collect())
+    }
+
+    fn values(grid: &GridCoefficients) -> Vec<f32> {
+        grid.coefficients.iter().map(|c| c[0][3]).collect()
+    }
+
+    #[test]
+    fn gaussian_blur_impulse() {
+        let grid = grid([5, 1, 1], &[0.0, 0.0, 1.0, 0.0, 0.0]);
+        let blurred = grid.gaussian_blur([1, 0, 0]);
+
+        // Weights of exp(-2) for neighbors and 1 for the center.
+        let w = (-2.0f32).exp();
+        let center = 1.0 / (1.0 + 2.0 * w);
+        let side = w / (1.0 + 2.0 * w);
+        let v = values(&blurred);
+        assert!((v[2] - center).abs() < 0.0001);
+        assert!((v[1] - side).abs() < 0.0001);
+        assert!((v[3] - side).abs() < 0.0001);
+        assert_eq!(0.0, v[0]);
+        assert_eq!(0.0, v[4]);
+        assert_ne!(0.0, blurred.unk6);
+    }
+
+    #[test]
+    fn gaussian_blur_zero_radius() {
+        let grid = grid([3, 1, 1], &[0.0, 1.0, 0.0]);
+        let blurred = grid.gaussian_blur([0, 0, 0]);
+        assert_eq!(grid.coefficients, blurred.coefficients);
+    }
+
+    #[test]
+    fn gaussian_blur_constant() {
+        // Clamped windows should preserve constant values at the edges.
+        let grid = grid([4, 3, 2], &[0.5; 24]);
+        let blurred = grid.gaussian_blur([2, 2, 2]);
+        for v in values(&blurred) {
+            assert!((v - 0.5).abs() < 0.0001);
+        }
+    }
+
+    #[test]
+    fn gaussian_blur_per_axis() {
+        // Values shouldn't be blurred along z.
+        let grid = grid([3, 1, 2], &[0.0, 1.0, 0.0, 2.0, 2.0, 2.0]);
+        let blurred = grid.gaussian_blur([1, 1, 0]);
+        let v = values(&blurred);
+        assert!(v[1] < 1.0 && v[0] > 0.0);
+        assert_eq!(v[0], v[2]);
+        assert!(v[3..].iter().all(|v| (v - 2.0).abs() < 0.0001));
+    }
+
+    #[test]
+    fn bilateral_filter_preserves_edges() {
+        let grid = grid([6, 1, 1], &[0.0, 0.0, 0.0, 1.0, 1.0, 1.0]);
+        let filtered = grid.bilateral_filter([2, 0, 0], 0.01);
+        for (a, b) in values(&grid).iter().zip(values(&filtered)) {
+            assert!((a - b).abs() < 0.0001);
+        }
+    }
+
+    #[test]
+    fn bilateral_filter_smooths_noise() {
+        let grid = grid([5, 1, 1], &[0.5, 0.5, 0.6, 0.5, 0.5]);
+        let filtered = grid.bilateral_filter([1, 0, 0], 1.0);
+        let v = values(&filtered);
+        assert!(v[2] < 0.6 && v[2] > 0.5);
+    }
+
+    #[test]
+    fn median_filter_removes_outliers() {
+        let grid = grid([5, 1, 1], &[1.0, 1.0, 100.0, 1.0, 1.0]);
+        let filtered = grid.median_filter([1, 0, 0]);
+        assert_eq!(vec![1.0; 5], values(&filtered));
+    }
+
+    #[test]
+    fn median_filter_preserves_edges() {
+        let grid = grid([6, 1, 1], &[0.0, 0.0, 0.0, 1.0, 1.0, 1.0]);
+        let filtered = grid.median_filter([1, 0, 0]);
+        assert_eq!(values(&grid), values(&filtered));
+    }
+
+    #[test]
+    fn median_filter_even_window() {
+        // The window for the first cell only has two cells.
+        let grid = grid([3, 1, 1], &[1.0, 2.0, 4.0]);
+        let filtered = grid.median_filter([1, 0, 0]);
+        assert_eq!(vec![1.5, 2.0, 3.0], values(&filtered));
+    }
+
+    #[test]
+    fn median_filter_2d() {
+        let grid = grid(
+            [3, 3, 2],
+            &[
+                0.0, 0.0, 0.0, 0.0, 9.0, 0.0, 0.0, 0.0, 0.0, //
+                5.0, 5.0, 5.0, 5.0, 5.0, 5.0, 5.0, 5.0, 5.0,
+            ],
+        );
+        let filtered = grid.median_filter([1, 1, 0]);
+        let v = values(&filtered);
+        assert_eq!(vec![0.0; 9], v[..9]);
+        assert_eq!(vec![5.0; 9], v[9..]);
+    }
+
+    #[test]
+    fn filter_missing_coefficients() {
+        let mut grid = grid([3, 1, 1], &[1.0, 2.0, 4.0]);
+        grid.coefficients.pop();
+        let filtered = grid.median_filter([1, 0, 0]);
+        assert_eq!(vec![1.5, 1.5], values(&filtered));
+    }
+}
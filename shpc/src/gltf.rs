@@ -0,0 +1,390 @@
+//! Export probe grids as glTF scenes for visualization in programs like Blender.
+//!
+//! Each probe is a small sphere at its world space position with vertex colors from its coefficients.
+//! Each TPCB is a separate node in the scene with a child node for each probe.
+use std::f32::consts::PI;
+use std::io::Write;
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::{sh, GridCoefficients, ShanFile};
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_SHORT: u32 = 5123;
+
+const SPHERE_RINGS: usize = 8;
+const SPHERE_SEGMENTS: usize = 16;
+
+/// The source of the vertex colors for each probe sphere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VertexColors {
+    /// The constant L0 color for all vertices.
+    #[default]
+    L0,
+    /// The L0 and L1 irradiance evaluated for each vertex normal.
+    Irradiance,
+}
+
+/// Settings for exporting probe grids to glTF.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GltfOptions {
+    /// The radius of each probe sphere.
+    /// Defaults to a quarter of the smallest grid spacing if not specified.
+    pub sphere_radius: Option<f32>,
+    pub vertex_colors: VertexColors,
+}
+
+/// Writes the probe grids in `file` to `path`.
+/// Paths with the extension ".glb" are written as binary glTF.
+/// Otherwise, the buffer data is written to a separate ".bin" file next to the ".gltf" file.
+pub fn write_gltf<P: AsRef<Path>>(
+    file: &ShanFile,
+    path: P,
+    options: &GltfOptions,
+) -> std::io::Result<()> {
+    let path = path.as_ref();
+    if path.extension().and_then(|e| e.to_str()) == Some("glb") {
+        let mut writer = std::fs::File::create(path)?;
+        write_glb(&mut writer, file, options)
+    } else {
+        let (mut json, buffer) = build_gltf(file, options);
+
+        let bin_path = path.with_extension("bin");
+        let uri = bin_path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        json["buffers"] = json!([{ "uri": uri, "byteLength": buffer.len() }]);
+
+        std::fs::write(&bin_path, &buffer)?;
+        std::fs::write(path, serde_json::to_string_pretty(&json)?)
+    }
+}
+
+/// Writes the probe grids in `file` as binary glTF to `writer`.
+pub fn write_glb<W: Write>(
+    writer: &mut W,
+    file: &ShanFile,
+    options: &GltfOptions,
+) -> std::io::Result<()> {
+    let (mut json, mut buffer) = build_gltf(file, options);
+    json["buffers"] = json!([{ "byteLength": buffer.len() }]);
+
+    // Chunks are padded to 4 bytes using spaces for JSON and zeros for binary data.
+    let mut json = serde_json::to_vec(&json)?;
+    json.resize(align4(json.len()), b' ');
+    buffer.resize(align4(buffer.len()), 0u8);
+
+    let length = 12 + 8 + json.len() + 8 + buffer.len();
+    writer.write_all(b"glTF")?;
+    writer.write_all(&2u32.to_le_bytes())?;
+    writer.write_all(&(length as u32).to_le_bytes())?;
+
+    writer.write_all(&(json.len() as u32).to_le_bytes())?;
+    writer.write_all(b"JSON")?;
+    writer.write_all(&json)?;
+
+    writer.write_all(&(buffer.len() as u32).to_le_bytes())?;
+    writer.write_all(b"BIN\0")?;
+    writer.write_all(&buffer)?;
+    Ok(())
+}
+
+fn build_gltf(file: &ShanFile, options: &GltfOptions) -> (Value, Vec<u8>) {
+    let mut builder = Builder::default();
+
+    let radius = options.sphere_radius.unwrap_or_else(|| {
+        file.tpcbs
+            .iter()
+            .map(|t| default_radius(&t.coefficients))
+            .reduce(f32::min)
+            .unwrap_or(1.0)
+    });
+    let (positions, normals, indices) = uv_sphere(radius);
+
+    let position_accessor = builder.add_accessor(
+        &positions,
+        FLOAT,
+        "VEC3",
+        ARRAY_BUFFER,
+        Some((
+            json!([-radius, -radius, -radius]),
+            json!([radius, radius, radius]),
+        )),
+    );
+    let normal_accessor = builder.add_accessor(&normals, FLOAT, "VEC3", ARRAY_BUFFER, None);
+    let index_accessor = builder.add_accessor(
+        &indices,
+        UNSIGNED_SHORT,
+        "SCALAR",
+        ELEMENT_ARRAY_BUFFER,
+        None,
+    );
+
+    let mut meshes = Vec::new();
+    let mut nodes = Vec::new();
+    let mut scene_nodes = Vec::new();
+    for (i, tpcb) in file.tpcbs.iter().enumerate() {
+        let grid = &tpcb.coefficients;
+
+        let mut children = Vec::new();
+        let [nx, ny, nz] = grid.grid_cell_count_xyz.map(|c| c as usize);
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx {
+                    let coefficients = match grid
+                        .cell_index(x, y, z)
+                        .and_then(|index| grid.coefficients.get(index))
+                    {
+                        Some(c) => c,
+                        None => continue,
+                    };
+
+                    let colors: Vec<_> = normals
+                        .iter()
+                        .map(|n| vertex_color(coefficients, *n, options.vertex_colors))
+                        .collect();
+                    let color_accessor =
+                        builder.add_accessor(&colors, FLOAT, "VEC3", ARRAY_BUFFER, None);
+
+                    meshes.push(json!({
+                        "primitives": [{
+                            "attributes": {
+                                "POSITION": position_accessor,
+                                "NORMAL": normal_accessor,
+                                "COLOR_0": color_accessor,
+                            },
+                            "indices": index_accessor,
+                        }]
+                    }));
+
+                    children.push(nodes.len());
+                    nodes.push(json!({
+                        "name": format!("probe_{}_{}_{}", x, y, z),
+                        "mesh": meshes.len() - 1,
+                        "translation": grid.cell_position(x, y, z),
+                    }));
+                }
+            }
+        }
+
+        scene_nodes.push(nodes.len());
+        nodes.push(json!({
+            "name": format!("tpcb{}_frame{}", i, tpcb.starting_frame),
+            "children": children,
+        }));
+    }
+
+    let json = json!({
+        "asset": { "version": "2.0", "generator": "shpc" },
+        "scene": 0,
+        "scenes": [{ "name": file.name, "nodes": scene_nodes }],
+        "nodes": nodes,
+        "meshes": meshes,
+        "accessors": builder.accessors,
+        "bufferViews": builder.buffer_views,
+    });
+    (json, builder.buffer)
+}
+
+fn default_radius(grid: &GridCoefficients) -> f32 {
+    let spacing = grid.spacing();
+    (0..3)
+        .filter(|i| grid.grid_cell_count_xyz[*i] > 1 && spacing[*i] > 0.0)
+        .map(|i| spacing[i] * 0.25)
+        .reduce(f32::min)
+        .unwrap_or(1.0)
+}
+
+fn vertex_color(coefficients: &[[f32; 4]; 3], normal: [f32; 3], colors: VertexColors) -> [f32; 3] {
+    coefficients.map(|c| match colors {
+        VertexColors::L0 => c[3].max(0.0),
+        VertexColors::Irradiance => sh::irradiance(c, normal).max(0.0),
+    })
+}
+
+// A sphere centered at the origin with positions, normals, and triangle indices.
+fn uv_sphere(radius: f32) -> (Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<u16>) {
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    for ring in 0..=SPHERE_RINGS {
+        let theta = PI * ring as f32 / SPHERE_RINGS as f32;
+        for segment in 0..=SPHERE_SEGMENTS {
+            let phi = 2.0 * PI * segment as f32 / SPHERE_SEGMENTS as f32;
+            let normal = [
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            ];
+            positions.push(normal.map(|n| n * radius));
+            normals.push(normal);
+        }
+    }
+
+    let mut indices = Vec::new();
+    for ring in 0..SPHERE_RINGS {
+        for segment in 0..SPHERE_SEGMENTS {
+            let a = (ring * (SPHERE_SEGMENTS + 1) + segment) as u16;
+            let b = a + SPHERE_SEGMENTS as u16 + 1;
+            // Use counterclockwise winding when viewed from outside the sphere.
+            indices.extend_from_slice(&[a, a + 1, b, a + 1, b + 1, b]);
+        }
+    }
+
+    (positions, normals, indices)
+}
+
+fn align4(n: usize) -> usize {
+    n.div_ceil(4) * 4
+}
+
+// Values that can be written to the glTF binary buffer.
+trait BufferData {
+    fn write_le(&self, buffer: &mut Vec<u8>);
+}
+
+impl BufferData for [f32; 3] {
+    fn write_le(&self, buffer: &mut Vec<u8>) {
+        for v in self {
+            buffer.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+}
+
+impl BufferData for u16 {
+    fn write_le(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    buffer: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl Builder {
+    fn add_accessor<T: BufferData>(
+        &mut self,
+        data: &[T],
+        component_type: u32,
+        accessor_type: &str,
+        target: u32,
+        min_max: Option<(Value, Value)>,
+    ) -> usize {
+        // Align each view to 4 bytes to satisfy the alignment of all component types.
+        self.buffer.resize(align4(self.buffer.len()), 0u8);
+        let offset = self.buffer.len();
+        for value in data {
+            value.write_le(&mut self.buffer);
+        }
+
+        self.buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": offset,
+            "byteLength": self.buffer.len() - offset,
+            "target": target,
+        }));
+
+        let mut accessor = json!({
+            "bufferView": self.buffer_views.len() - 1,
+            "componentType": component_type,
+            "count": data.len(),
+            "type": accessor_type,
+        });
+        if let Some((min, max)) = min_max {
+            accessor["min"] = min;
+            accessor["max"] = max;
+        }
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{grid, shan_file};
+
+    fn file() -> ShanFile {
+        let grid = GridCoefficients {
+            grid_range_max_xyz: [4.0, 4.0, 0.0],
+            ..grid([2, 3, 1], vec![[[0.5, 0.0, 0.0, 1.0]; 3]; 6])
+        };
+        shan_file(vec![(0, grid.clone()), (10, grid)])
+    }
+
+    #[test]
+    fn sphere_normals_are_unit_length() {
+        let (positions, normals, indices) = uv_sphere(2.0);
+        assert_eq!(positions.len(), normals.len());
+        assert_eq!(SPHERE_RINGS * SPHERE_SEGMENTS * 6, indices.len());
+        for n in normals {
+            let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+            assert!((length - 1.0).abs() < 0.0001);
+        }
+    }
+
+    #[test]
+    fn gltf_nodes_per_tpcb() {
+        let (json, buffer) = build_gltf(&file(), &GltfOptions::default());
+
+        let scene_nodes = json["scenes"][0]["nodes"].as_array().unwrap();
+        assert_eq!(2, scene_nodes.len());
+        let tpcb_node = &json["nodes"][scene_nodes[1].as_u64().unwrap() as usize];
+        assert_eq!("tpcb1_frame10", tpcb_node["name"]);
+        assert_eq!(6, tpcb_node["children"].as_array().unwrap().len());
+
+        assert_eq!(12, json["meshes"].as_array().unwrap().len());
+        assert_eq!(json!([4.0, 2.0, 0.0]), json["nodes"][3]["translation"]);
+
+        // The default radius is based on the smallest spacing.
+        assert_eq!(json!([0.5, 0.5, 0.5]), json["accessors"][0]["max"]);
+
+        let view = json["bufferViews"].as_array().unwrap().last().unwrap();
+        assert_eq!(
+            buffer.len() as u64,
+            view["byteOffset"].as_u64().unwrap() + view["byteLength"].as_u64().unwrap()
+        );
+    }
+
+    #[test]
+    fn vertex_colors_irradiance() {
+        let c = [[0.5, 0.0, 0.0, 1.0]; 3];
+        assert_eq!(
+            [1.0; 3],
+            vertex_color(&c, [1.0, 0.0, 0.0], VertexColors::L0)
+        );
+        assert_eq!(
+            [1.5; 3],
+            vertex_color(&c, [1.0, 0.0, 0.0], VertexColors::Irradiance)
+        );
+        assert_eq!(
+            [0.5; 3],
+            vertex_color(&c, [-1.0, 0.0, 0.0], VertexColors::Irradiance)
+        );
+    }
+
+    #[test]
+    fn glb_header_and_chunks() {
+        let mut glb = Vec::new();
+        write_glb(&mut glb, &file(), &GltfOptions::default()).unwrap();
+
+        assert_eq!(b"glTF", &glb[0..4]);
+        assert_eq!(
+            glb.len() as u32,
+            u32::from_le_bytes(glb[8..12].try_into().unwrap())
+        );
+        assert_eq!(b"JSON", &glb[16..20]);
+
+        let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        assert_eq!(0, json_length % 4);
+        let json: Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+        assert_eq!("2.0", json["asset"]["version"]);
+        assert_eq!(b"BIN\0", &glb[24 + json_length..28 + json_length]);
+    }
+}
@@ -0,0 +1,490 @@
+//! Spatial operations on the probe grid like sampling, cropping, and merging.
+//! Cells are positioned in world space from `grid_range_min_xyz` to `grid_range_max_xyz`
+//! and stored in row-major order for x -> y -> z.
+use crate::{sh, GridCoefficients};
+
+// Tolerance for floating point errors when comparing positions to cell boundaries.
+const EPSILON: f32 = 0.0001;
+
+impl GridCoefficients {
+    /// The distance between adjacent cells along each axis.
+    /// Axes with at most one cell use a spacing of `1.0` to match the values in game.
+    pub fn spacing(&self) -> [f32; 3] {
+        let mut spacing = [1.0; 3];
+        for (i, spacing) in spacing.iter_mut().enumerate() {
+            if self.grid_cell_count_xyz[i] > 1 {
+                *spacing = (self.grid_range_max_xyz[i] - self.grid_range_min_xyz[i])
+                    / (self.grid_cell_count_xyz[i] as f32 - 1.0);
+            }
+        }
+        spacing
+    }
+
+    /// The index into `coefficients` for the cell at `(x, y, z)`
+    /// or `None` if the cell is outside the grid.
+    pub fn cell_index(&self, x: usize, y: usize, z: usize) -> Option<usize> {
+        let [nx, ny, nz] = self.grid_cell_count_xyz.map(|c| c as usize);
+        if x < nx && y < ny && z < nz {
+            Some(x + nx * (y + ny * z))
+        } else {
+            None
+        }
+    }
+
+    /// The `(x, y, z)` of the cell at `index` in `coefficients`
+    /// or `None` if the index is outside the grid.
+    pub fn cell_xyz(&self, index: usize) -> Option<[usize; 3]> {
+        let [nx, ny, nz] = self.grid_cell_count_xyz.map(|c| c as usize);
+        if index < nx * ny * nz {
+            Some([index % nx, (index / nx) % ny, index / (nx * ny)])
+        } else {
+            None
+        }
+    }
+
+    /// The world space position of the cell at `(x, y, z)`.
+    pub fn cell_position(&self, x: usize, y: usize, z: usize) -> [f32; 3] {
+        let spacing = self.spacing();
+        let xyz = [x, y, z];
+        let mut position = self.grid_range_min_xyz;
+        for i in 0..3 {
+            position[i] += xyz[i] as f32 * spacing[i];
+        }
+        position
+    }
+
+    /// Returns `true` if `position` is within the bounds of the grid.
+    pub fn contains(&self, position: [f32; 3]) -> bool {
+        (0..3).all(|i| {
+            position[i] >= self.grid_range_min_xyz[i] - EPSILON
+                && position[i] <= self.grid_range_max_xyz[i] + EPSILON
+        })
+    }
+
+    /// Trilinearly interpolates the coefficients at `position`.
+    /// Positions outside the grid are clamped to the nearest boundary.
+    /// Returns `None` if the grid has no cells.
+    pub fn sample(&self, position: [f32; 3]) -> Option<[[f32; 4]; 3]> {
+        let spacing = self.spacing();
+
+        // Find the two neighboring cells and the interpolation factor for each axis.
+        let mut neighbors = [[0usize; 2]; 3];
+        let mut factors = [0.0f32; 3];
+        for i in 0..3 {
+            let count = self.grid_cell_count_xyz[i] as usize;
+            if count == 0 {
+                return None;
+            }
+            if count > 1 {
+                let f = ((position[i] - self.grid_range_min_xyz[i]) / spacing[i])
+                    .clamp(0.0, count as f32 - 1.0);
+                let i0 = (f.floor() as usize).min(count - 2);
+                neighbors[i] = [i0, i0 + 1];
+                factors[i] = f - i0 as f32;
+            }
+        }
+
+        let mut result = [[0.0; 4]; 3];
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut xyz = [0; 3];
+            for i in 0..3 {
+                let upper = (corner >> i) & 1;
+                xyz[i] = neighbors[i][upper];
+                weight *= if upper == 1 {
+                    factors[i]
+                } else {
+                    1.0 - factors[i]
+                };
+            }
+
+            if weight > 0.0 {
+                let index = self.cell_index(xyz[0], xyz[1], xyz[2])?;
+                add_scaled(&mut result, self.coefficients.get(index)?, weight);
+            }
+        }
+        Some(result)
+    }
+
+    /// Extracts the cells with positions in the region from `min` to `max`.
+    /// The new bounds are snapped to the cells contained in the region.
+    /// Returns `None` if the region doesn't contain any cells.
+    pub fn crop(&self, min: [f32; 3], max: [f32; 3]) -> Option<Self> {
+        let spacing = self.spacing();
+
+        let mut start = [0usize; 3];
+        let mut end = [0usize; 3];
+        for i in 0..3 {
+            let count = self.grid_cell_count_xyz[i] as usize;
+            if count == 0 {
+                return None;
+            }
+
+            let first = ((min[i] - self.grid_range_min_xyz[i]) / spacing[i] - EPSILON)
+                .ceil()
+                .max(0.0);
+            let last = ((max[i] - self.grid_range_min_xyz[i]) / spacing[i] + EPSILON)
+                .floor()
+                .min(count as f32 - 1.0);
+            if first > last {
+                return None;
+            }
+            start[i] = first as usize;
+            end[i] = last as usize;
+        }
+
+        let mut coefficients = Vec::new();
+        for z in start[2]..=end[2] {
+            for y in start[1]..=end[1] {
+                for x in start[0]..=end[0] {
+                    let index = self.cell_index(x, y, z)?;
+                    coefficients.push(*self.coefficients.get(index)?);
+                }
+            }
+        }
+
+        Some(Self {
+            grid_cell_count_xyz: [0, 1, 2].map(|i| (end[i] - start[i] + 1) as u32),
+            grid_range_min_xyz: self.cell_position(start[0], start[1], start[2]),
+            grid_range_max_xyz: self.cell_position(end[0], end[1], end[2]),
+            unk5: self.unk5,
+            unk6: self.unk6,
+            coefficients,
+        })
+    }
+
+    /// Composites `self` and `other` into a new grid covering the union of their bounds.
+    /// The new grid uses the smaller spacing of the two grids for each axis.
+    ///
+    /// In overlapping regions, `other` is blended over `self`
+    /// with a linear falloff over a distance of `falloff` from the boundary of `other`.
+    /// A `falloff` of `0.0` replaces the overlapping region with `other`.
+    /// Cells covered by neither grid use the values of the nearest grid.
+    ///
+    /// The `unk5` and `unk6` values are recalculated to fit the merged coefficients.
+    pub fn merge(&self, other: &Self, falloff: f32) -> Self {
+        let mut merged = self.union_layout(other);
+
+        let [nx, ny, nz] = merged.grid_cell_count_xyz.map(|c| c as usize);
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx {
+                    let position = merged.cell_position(x, y, z);
+                    let value = match (self.contains(position), other.contains(position)) {
+                        (true, true) => {
+                            let t = other.edge_weight(position, falloff);
+                            lerp_cell(
+                                self.sample(position).unwrap_or_default(),
+                                other.sample(position).unwrap_or_default(),
+                                t,
+                            )
+                        }
+                        (true, false) => self.sample(position).unwrap_or_default(),
+                        (false, true) => other.sample(position).unwrap_or_default(),
+                        (false, false) => {
+                            let nearest = if self.distance(position) <= other.distance(position) {
+                                self
+                            } else {
+                                other
+                            };
+                            nearest.sample(position).unwrap_or_default()
+                        }
+                    };
+                    merged.coefficients.push(value);
+                }
+            }
+        }
+
+        merged.recalculate_unk5_unk6();
+        merged
+    }
+
+    /// Linearly interpolates between `self` at `t = 0.0` and `other` at `t = 1.0`.
+    /// Grids with different cell counts or bounds are resampled
+    /// to a common grid covering the union of their bounds.
+    ///
+    /// The `unk5` and `unk6` values are recalculated to fit the interpolated coefficients.
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        if !self.has_same_layout(other) {
+            let layout = self.union_layout(other);
+            return self.resample(&layout).lerp(&other.resample(&layout), t);
+        }
+
+        let mut result = Self {
+            grid_cell_count_xyz: self.grid_cell_count_xyz,
+            grid_range_min_xyz: self.grid_range_min_xyz,
+            grid_range_max_xyz: self.grid_range_max_xyz,
+            unk5: self.unk5,
+            unk6: self.unk6,
+            coefficients: self
+                .coefficients
+                .iter()
+                .zip(other.coefficients.iter())
+                .map(|(a, b)| lerp_cell(*a, *b, t))
+                .collect(),
+        };
+        result.recalculate_unk5_unk6();
+        result
+    }
+
+    /// Samples the coefficients at the cell positions of `layout`.
+    /// The `unk5` and `unk6` values are recalculated to fit the new coefficients.
+    pub fn resample(&self, layout: &Self) -> Self {
+        let mut result = Self {
+            grid_cell_count_xyz: layout.grid_cell_count_xyz,
+            grid_range_min_xyz: layout.grid_range_min_xyz,
+            grid_range_max_xyz: layout.grid_range_max_xyz,
+            unk5: self.unk5,
+            unk6: self.unk6,
+            coefficients: Vec::new(),
+        };
+
+        let [nx, ny, nz] = layout.grid_cell_count_xyz.map(|c| c as usize);
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx {
+                    let position = layout.cell_position(x, y, z);
+                    result
+                        .coefficients
+                        .push(self.sample(position).unwrap_or_default());
+                }
+            }
+        }
+
+        result.recalculate_unk5_unk6();
+        result
+    }
+
+    /// The maximum [irradiance](crate::sh::irradiance) difference for any cell, channel, and normal
+    /// or infinity if the grids have different layouts.
+    pub fn max_irradiance_difference(&self, other: &Self) -> f32 {
+        if !self.has_same_layout(other) {
+            return f32::INFINITY;
+        }
+
+        self.coefficients
+            .iter()
+            .zip(other.coefficients.iter())
+            .flat_map(|(a, b)| a.iter().zip(b.iter()))
+            .map(|(a, b)| sh::max_irradiance_difference(*a, *b))
+            .fold(0.0, f32::max)
+    }
+
+    /// Returns `true` if both grids have the same cell counts, bounds, and number of coefficients.
+    pub fn has_same_layout(&self, other: &Self) -> bool {
+        self.grid_cell_count_xyz == other.grid_cell_count_xyz
+            && self.grid_range_min_xyz == other.grid_range_min_xyz
+            && self.grid_range_max_xyz == other.grid_range_max_xyz
+            && self.coefficients.len() == other.coefficients.len()
+    }
+
+    // An empty grid covering the union of both bounds using the smaller spacing for each axis.
+    pub(crate) fn union_layout(&self, other: &Self) -> Self {
+        let mut grid_cell_count_xyz = [1; 3];
+        let mut grid_range_min_xyz = [0.0; 3];
+        let mut grid_range_max_xyz = [0.0; 3];
+
+        let spacing_a = self.spacing();
+        let spacing_b = other.spacing();
+        for i in 0..3 {
+            let min = self.grid_range_min_xyz[i].min(other.grid_range_min_xyz[i]);
+            let max = self.grid_range_max_xyz[i].max(other.grid_range_max_xyz[i]);
+
+            let step = [
+                (self.grid_cell_count_xyz[i], spacing_a[i]),
+                (other.grid_cell_count_xyz[i], spacing_b[i]),
+            ]
+            .iter()
+            .filter(|(count, spacing)| *count > 1 && *spacing > 0.0)
+            .map(|(_, spacing)| *spacing)
+            .reduce(f32::min)
+            .unwrap_or(max - min);
+
+            grid_range_min_xyz[i] = min;
+            grid_range_max_xyz[i] = min;
+            if max - min > EPSILON && step > 0.0 {
+                // Round up to a whole number of cells to preserve the spacing.
+                let steps = ((max - min) / step - EPSILON).ceil();
+                grid_cell_count_xyz[i] = steps as u32 + 1;
+                grid_range_max_xyz[i] = min + steps * step;
+            }
+        }
+
+        Self {
+            grid_cell_count_xyz,
+            grid_range_min_xyz,
+            grid_range_max_xyz,
+            unk5: self.unk5,
+            unk6: self.unk6,
+            coefficients: Vec::new(),
+        }
+    }
+
+    // The distance from position to the grid bounds or 0.0 if position is inside the grid.
+    fn distance(&self, position: [f32; 3]) -> f32 {
+        (0..3)
+            .map(|i| {
+                let clamped = position[i].clamp(
+                    self.grid_range_min_xyz[i],
+                    self.grid_range_max_xyz[i].max(self.grid_range_min_xyz[i]),
+                );
+                (position[i] - clamped).powi(2)
+            })
+            .sum::<f32>()
+            .sqrt()
+    }
+
+    // A weight from 0.0 at the boundary to 1.0 at a distance of falloff inside the grid.
+    // Axes without any extent don't have a boundary to fade out.
+    fn edge_weight(&self, position: [f32; 3], falloff: f32) -> f32 {
+        if falloff <= 0.0 {
+            return 1.0;
+        }
+
+        (0..3)
+            .filter(|i| self.grid_range_max_xyz[*i] - self.grid_range_min_xyz[*i] > EPSILON)
+            .map(|i| {
+                (position[i] - self.grid_range_min_xyz[i])
+                    .min(self.grid_range_max_xyz[i] - position[i])
+            })
+            .map(|d| (d / falloff).clamp(0.0, 1.0))
+            .reduce(f32::min)
+            .unwrap_or(1.0)
+    }
+}
+
+pub(crate) fn add_scaled(result: &mut [[f32; 4]; 3], value: &[[f32; 4]; 3], weight: f32) {
+    for (r, v) in result.iter_mut().zip(value.iter()) {
+        for (r, v) in r.iter_mut().zip(v.iter()) {
+            *r += v * weight;
+        }
+    }
+}
+
+pub(crate) fn lerp_cell(a: [[f32; 4]; 3], b: [[f32; 4]; 3], t: f32) -> [[f32; 4]; 3] {
+    let mut result = [[0.0; 4]; 3];
+    add_scaled(&mut result, &a, 1.0 - t);
+    add_scaled(&mut result, &b, t);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::grid;
+
+    fn cell(value: f32) -> [[f32; 4]; 3] {
+        [[0.0, 0.0, 0.0, value]; 3]
+    }
+
+    fn grid_2d(min: [f32; 3], max: [f32; 3], count: [u32; 3], value: f32) -> GridCoefficients {
+        let coefficients = (0..count.iter().product::<u32>())
+            .map(|i| cell(value + i as f32))
+            .collect();
+        GridCoefficients {
+            grid_range_min_xyz: min,
+            grid_range_max_xyz: max,
+            ..grid(count, coefficients)
+        }
+    }
+
+    #[test]
+    fn spacing_training() {
+        let grid = GridCoefficients {
+            grid_range_min_xyz: [-563.37305, -98.03044, 0.0],
+            grid_range_max_xyz: [65.51749, 127.32863, 0.0],
+            ..grid([21, 10, 1], vec![[[0.0; 4]; 3]; 210])
+        };
+        assert_eq!([31.444525, 25.039896, 1.0], grid.spacing());
+        assert_eq!(Some(209), grid.cell_index(20, 9, 0));
+        assert_eq!(None, grid.cell_index(21, 0, 0));
+        assert_eq!(Some([20, 9, 0]), grid.cell_xyz(209));
+        assert_eq!(Some([1, 1, 0]), grid.cell_xyz(22));
+        assert_eq!(None, grid.cell_xyz(210));
+    }
+
+    #[test]
+    fn sample_cells_and_midpoints() {
+        let grid = grid_2d([0.0, 0.0, 0.0], [2.0, 1.0, 0.0], [3, 2, 1], 0.0);
+        assert_eq!(Some(cell(0.0)), grid.sample([0.0, 0.0, 0.0]));
+        assert_eq!(Some(cell(5.0)), grid.sample([2.0, 1.0, 0.0]));
+        assert_eq!(Some(cell(0.5)), grid.sample([0.5, 0.0, 0.0]));
+        assert_eq!(Some(cell(2.5)), grid.sample([1.0, 0.5, 0.0]));
+        // Positions are clamped to the grid.
+        assert_eq!(Some(cell(5.0)), grid.sample([10.0, 10.0, 10.0]));
+    }
+
+    #[test]
+    fn sample_empty_grid() {
+        let grid = grid_2d([0.0; 3], [0.0; 3], [0, 0, 0], 0.0);
+        assert_eq!(None, grid.sample([0.0; 3]));
+    }
+
+    #[test]
+    fn crop_snaps_to_cells() {
+        let grid = grid_2d([0.0, 0.0, 0.0], [3.0, 2.0, 0.0], [4, 3, 1], 0.0);
+        let cropped = grid.crop([0.5, 0.5, -1.0], [2.5, 2.0, 1.0]).unwrap();
+        assert_eq!([2, 2, 1], cropped.grid_cell_count_xyz);
+        assert_eq!([1.0, 1.0, 0.0], cropped.grid_range_min_xyz);
+        assert_eq!([2.0, 2.0, 0.0], cropped.grid_range_max_xyz);
+        assert_eq!(
+            vec![cell(5.0), cell(6.0), cell(9.0), cell(10.0)],
+            cropped.coefficients
+        );
+    }
+
+    #[test]
+    fn crop_outside_grid() {
+        let grid = grid_2d([0.0, 0.0, 0.0], [3.0, 2.0, 0.0], [4, 3, 1], 0.0);
+        assert_eq!(None, grid.crop([4.0, 0.0, 0.0], [5.0, 2.0, 0.0]));
+        assert_eq!(None, grid.crop([0.2, 0.0, 0.0], [0.8, 2.0, 0.0]));
+    }
+
+    #[test]
+    fn merge_disjoint_grids() {
+        let a = grid_2d([0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [2, 1, 1], 1.0);
+        let b = grid_2d([3.0, 0.0, 0.0], [4.0, 0.0, 0.0], [2, 1, 1], 3.0);
+        let merged = a.merge(&b, 0.0);
+        assert_eq!([5, 1, 1], merged.grid_cell_count_xyz);
+        assert_eq!([0.0, 0.0, 0.0], merged.grid_range_min_xyz);
+        assert_eq!([4.0, 0.0, 0.0], merged.grid_range_max_xyz);
+
+        let l0: Vec<_> = merged.coefficients.iter().map(|c| c[0][3]).collect();
+        assert_eq!(vec![1.0, 2.0, 2.0, 3.0, 4.0], l0);
+    }
+
+    #[test]
+    fn merge_overlap_falloff() {
+        let a = grid_2d([0.0, 0.0, 0.0], [4.0, 0.0, 0.0], [5, 1, 1], 0.0);
+        let mut b = grid_2d([0.0, 0.0, 0.0], [4.0, 0.0, 0.0], [5, 1, 1], 0.0);
+        b.coefficients = vec![cell(10.0); 5];
+
+        let replaced = a.merge(&b, 0.0);
+        assert!(replaced.coefficients.iter().all(|c| c[0][3] == 10.0));
+
+        // The weight increases linearly from the boundary of b.
+        let blended = a.merge(&b, 2.0);
+        let l0: Vec<_> = blended.coefficients.iter().map(|c| c[0][3]).collect();
+        assert_eq!(vec![0.0, 5.5, 10.0, 6.5, 4.0], l0);
+    }
+
+    #[test]
+    fn lerp_same_layout() {
+        let a = grid_2d([0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [2, 1, 1], 0.0);
+        let b = grid_2d([0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [2, 1, 1], 4.0);
+        let result = a.lerp(&b, 0.25);
+        assert_eq!([2, 1, 1], result.grid_cell_count_xyz);
+        assert_eq!(vec![cell(1.0), cell(2.0)], result.coefficients);
+    }
+
+    #[test]
+    fn lerp_different_layouts() {
+        let a = grid_2d([0.0, 0.0, 0.0], [2.0, 0.0, 0.0], [2, 1, 1], 0.0);
+        let b = grid_2d([0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [2, 1, 1], 2.0);
+        let result = a.lerp(&b, 0.5);
+        assert_eq!([3, 1, 1], result.grid_cell_count_xyz);
+        assert_eq!([2.0, 0.0, 0.0], result.grid_range_max_xyz);
+        let l0: Vec<_> = result.coefficients.iter().map(|c| c[0][3]).collect();
+        assert_eq!(vec![1.0, 1.75, 2.0], l0);
+    }
+}
@@ -0,0 +1,422 @@
+//! Floating point images of the probe data for viewing and comparison.
+//!
+//! Images can be saved as Radiance `.hdr` to preserve the exact linear values
+//! or as tone-mapped sRGB `.png` for viewing in any image viewer.
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+
+use crate::{sh, GridCoefficients};
+
+/// The plane for 2D slices through a 3D probe grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SlicePlane {
+    /// Slices along the Z axis with X as the horizontal and Y as the vertical axis.
+    #[default]
+    XY,
+    /// Slices along the Y axis with X as the horizontal and Z as the vertical axis.
+    XZ,
+    /// Slices along the X axis with Y as the horizontal and Z as the vertical axis.
+    YZ,
+}
+
+impl SlicePlane {
+    // The horizontal, vertical, and slice axis indices.
+    fn axes(&self) -> [usize; 3] {
+        match self {
+            SlicePlane::XY => [0, 1, 2],
+            SlicePlane::XZ => [0, 2, 1],
+            SlicePlane::YZ => [1, 2, 0],
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            SlicePlane::XY => "xy",
+            SlicePlane::XZ => "xz",
+            SlicePlane::YZ => "yz",
+        }
+    }
+}
+
+/// The value to display for each probe.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SliceValue {
+    /// The constant L0 color.
+    #[default]
+    L0,
+    /// The L0 and L1 irradiance for a surface with the given normal.
+    Irradiance([f32; 3]),
+}
+
+impl SliceValue {
+    /// Evaluates the linear RGB color for the coefficients of a single cell.
+    pub fn evaluate(&self, coefficients: &[[f32; 4]; 3]) -> [f32; 3] {
+        match self {
+            SliceValue::L0 => coefficients.map(|c| c[3]),
+            SliceValue::Irradiance(normal) => coefficients.map(|c| sh::irradiance(c, *normal)),
+        }
+    }
+}
+
+/// An image with linear RGB float pixels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    /// The `width * height` pixels in row-major order starting from the top left.
+    pub data: Vec<[f32; 3]>,
+}
+
+impl HdrImage {
+    /// Creates an image with all pixels set to `color`.
+    pub fn new(width: u32, height: u32, color: [f32; 3]) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![color; width as usize * height as usize],
+        }
+    }
+
+    /// Writes the image to `path` as Radiance HDR or tone-mapped PNG depending on the extension.
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let path = path.as_ref();
+        // Check the extension first to avoid leaving an empty file on errors.
+        let write = match path.extension().and_then(|e| e.to_str()) {
+            Some("hdr") => Self::write_hdr,
+            Some("png") => Self::write_png,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Expected a .hdr or .png file extension",
+                ))
+            }
+        };
+        let mut writer = BufWriter::new(std::fs::File::create(path)?);
+        write(self, &mut writer)
+    }
+
+    /// Writes the image as Radiance HDR with RGBE pixels.
+    /// Negative values are clamped to zero since RGBE can't represent them.
+    pub fn write_hdr<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        write!(
+            writer,
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            self.height, self.width
+        )?;
+
+        for row in self.data.chunks(self.width.max(1) as usize) {
+            let pixels: Vec<_> = row.iter().map(|p| rgbe(*p)).collect();
+            // Readers expect the run length encoding for widths in this range.
+            // Some readers treat flat scanlines starting with 2, 2 as encoded,
+            // so always use the encoding when possible.
+            if (8..0x8000).contains(&self.width) {
+                writer.write_all(&[2, 2, (self.width >> 8) as u8, self.width as u8])?;
+                for component in 0..4 {
+                    let values: Vec<_> = pixels.iter().map(|p| p[component]).collect();
+                    // Only use literal runs for simplicity.
+                    for chunk in values.chunks(128) {
+                        writer.write_all(&[chunk.len() as u8])?;
+                        writer.write_all(chunk)?;
+                    }
+                }
+            } else {
+                for pixel in pixels {
+                    writer.write_all(&pixel)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the image as an 8-bit sRGB PNG after applying [tone_map] to each pixel.
+    pub fn write_png<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let bytes: Vec<_> = self.data.iter().flat_map(|p| tone_map(*p)).collect();
+        encoder
+            .write_header()
+            .and_then(|mut w| w.write_image_data(&bytes))
+            .map_err(Error::other)
+    }
+}
+
+/// Converts a linear color to 8-bit sRGB using the Reinhard operator `x / (1 + x)`.
+/// Negative values are clamped to zero.
+pub fn tone_map(color: [f32; 3]) -> [u8; 3] {
+    color.map(|c| {
+        let c = c.max(0.0);
+        let c = if c.is_finite() { c / (1.0 + c) } else { 1.0 };
+        let srgb = if c <= 0.0031308 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        };
+        (srgb * 255.0).round().clamp(0.0, 255.0) as u8
+    })
+}
+
+// Shared exponent encoding with an 8-bit mantissa for each channel.
+fn rgbe(color: [f32; 3]) -> [u8; 4] {
+    let [r, g, b] = color.map(|c| if c.is_finite() { c.max(0.0) } else { 0.0 });
+    let max = r.max(g).max(b);
+    if max < 1e-32 {
+        return [0; 4];
+    }
+
+    // Find the exponent e such that max = m * 2^e for a mantissa m in [0.5, 1).
+    let mut exponent = max.log2().floor() as i32 + 1;
+    if max / 2f32.powi(exponent) >= 1.0 {
+        exponent += 1;
+    }
+    let scale = 256.0 / 2f32.powi(exponent);
+    [
+        (r * scale).min(255.0) as u8,
+        (g * scale).min(255.0) as u8,
+        (b * scale).min(255.0) as u8,
+        (exponent + 128).clamp(0, 255) as u8,
+    ]
+}
+
+impl GridCoefficients {
+    /// The number of slices for `plane`.
+    pub fn slice_count(&self, plane: SlicePlane) -> usize {
+        self.grid_cell_count_xyz[plane.axes()[2]] as usize
+    }
+
+    /// Creates an image with one pixel per cell for the slice at `index` along the axis normal to `plane`.
+    /// The vertical axis increases from the bottom to the top of the image.
+    /// Returns `None` if `index` is out of range or there are too few coefficients.
+    pub fn slice(&self, plane: SlicePlane, index: usize, value: SliceValue) -> Option<HdrImage> {
+        let [u_axis, v_axis, _] = plane.axes();
+        if index >= self.slice_count(plane) {
+            return None;
+        }
+
+        let width = self.grid_cell_count_xyz[u_axis];
+        let height = self.grid_cell_count_xyz[v_axis];
+        let mut image = HdrImage::new(width, height, [0.0; 3]);
+        for row in 0..height as usize {
+            for u in 0..width as usize {
+                let v = height as usize - 1 - row;
+                let xyz = match plane {
+                    SlicePlane::XY => [u, v, index],
+                    SlicePlane::XZ => [u, index, v],
+                    SlicePlane::YZ => [index, u, v],
+                };
+                let cell = self.cell_index(xyz[0], xyz[1], xyz[2])?;
+                image.data[row * width as usize + u] = value.evaluate(self.coefficients.get(cell)?);
+            }
+        }
+        Some(image)
+    }
+
+    /// Writes every slice for `plane` to `directory` as both `.hdr` and `.png`.
+    /// Files are named like `{name}_xy_0.hdr` and `{name}_xy_0.png` for each slice index.
+    pub fn write_slices<P: AsRef<Path>>(
+        &self,
+        directory: P,
+        name: &str,
+        plane: SlicePlane,
+        value: SliceValue,
+    ) -> std::io::Result<()> {
+        let directory = directory.as_ref();
+        std::fs::create_dir_all(directory)?;
+        for index in 0..self.slice_count(plane) {
+            let image = self.slice(plane, index, value).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    "The coefficient count does not match the grid cell count",
+                )
+            })?;
+            for extension in ["hdr", "png"] {
+                let file_name = format!("{}_{}_{}.{}", name, plane.name(), index, extension);
+                image.write_to_file(directory.join(file_name))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures;
+
+    fn grid() -> GridCoefficients {
+        // Store the cell index in the red L0 and the z coordinate in the red L1 x component.
+        let coefficients = (0..12)
+            .map(|i| {
+                [
+                    [(i / 6) as f32, 0.0, 0.0, i as f32],
+                    [0.0, 0.0, 0.0, 0.5],
+                    [0.0, 0.0, 0.0, 0.0],
+                ]
+            })
+            .collect();
+        test_fixtures::grid([3, 2, 2], coefficients)
+    }
+
+    fn red(image: &HdrImage) -> Vec<f32> {
+        image.data.iter().map(|p| p[0]).collect()
+    }
+
+    // Decode the literal runs written by write_hdr.
+    fn decode_hdr_scanline(bytes: &[u8], width: usize) -> Vec<[u8; 4]> {
+        assert_eq!(&[2, 2, (width >> 8) as u8, width as u8], &bytes[..4]);
+        let mut pixels = vec![[0u8; 4]; width];
+        let mut offset = 4;
+        for component in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let count = bytes[offset] as usize;
+                assert!(count <= 128);
+                for (pixel, value) in pixels[x..x + count]
+                    .iter_mut()
+                    .zip(&bytes[offset + 1..offset + 1 + count])
+                {
+                    pixel[component] = *value;
+                }
+                offset += 1 + count;
+                x += count;
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn slice_count() {
+        let grid = grid();
+        assert_eq!(2, grid.slice_count(SlicePlane::XY));
+        assert_eq!(2, grid.slice_count(SlicePlane::XZ));
+        assert_eq!(3, grid.slice_count(SlicePlane::YZ));
+    }
+
+    #[test]
+    fn slice_xy_l0() {
+        let image = grid().slice(SlicePlane::XY, 1, SliceValue::L0).unwrap();
+        assert_eq!((3, 2), (image.width, image.height));
+        // The top row has the highest y value.
+        assert_eq!(vec![9.0, 10.0, 11.0, 6.0, 7.0, 8.0], red(&image));
+        assert_eq!([6.0, 0.5, 0.0], image.data[3]);
+    }
+
+    #[test]
+    fn slice_xz_l0() {
+        let image = grid().slice(SlicePlane::XZ, 1, SliceValue::L0).unwrap();
+        assert_eq!((3, 2), (image.width, image.height));
+        assert_eq!(vec![9.0, 10.0, 11.0, 3.0, 4.0, 5.0], red(&image));
+    }
+
+    #[test]
+    fn slice_yz_l0() {
+        let image = grid().slice(SlicePlane::YZ, 2, SliceValue::L0).unwrap();
+        assert_eq!((2, 2), (image.width, image.height));
+        assert_eq!(vec![8.0, 11.0, 2.0, 5.0], red(&image));
+    }
+
+    #[test]
+    fn slice_irradiance() {
+        let image = grid()
+            .slice(SlicePlane::XY, 1, SliceValue::Irradiance([1.0, 0.0, 0.0]))
+            .unwrap();
+        assert_eq!(vec![10.0, 11.0, 12.0, 7.0, 8.0, 9.0], red(&image));
+
+        let image = grid()
+            .slice(SlicePlane::XY, 1, SliceValue::Irradiance([-1.0, 0.0, 0.0]))
+            .unwrap();
+        assert_eq!(vec![8.0, 9.0, 10.0, 5.0, 6.0, 7.0], red(&image));
+    }
+
+    #[test]
+    fn slice_out_of_range() {
+        assert_eq!(None, grid().slice(SlicePlane::XY, 2, SliceValue::L0));
+        assert_eq!(None, grid().slice(SlicePlane::YZ, 3, SliceValue::L0));
+    }
+
+    #[test]
+    fn slice_missing_coefficients() {
+        let mut grid = grid();
+        grid.coefficients.truncate(5);
+        assert_eq!(None, grid.slice(SlicePlane::XY, 0, SliceValue::L0));
+    }
+
+    #[test]
+    fn rgbe_values() {
+        assert_eq!([0, 0, 0, 0], rgbe([0.0, 0.0, 0.0]));
+        assert_eq!([0, 0, 0, 0], rgbe([-1.0, f32::NAN, 0.0]));
+        assert_eq!([128, 64, 0, 129], rgbe([1.0, 0.5, 0.0]));
+        assert_eq!([128, 0, 0, 128], rgbe([0.5, 0.0, 0.0]));
+        assert_eq!([0, 0, 160, 131], rgbe([0.0, 0.0, 5.0]));
+    }
+
+    #[test]
+    fn tone_map_values() {
+        assert_eq!([0, 0, 0], tone_map([0.0, -1.0, f32::NAN]));
+        assert_eq!([188, 255, 255], tone_map([1.0, f32::INFINITY, 1e10]));
+    }
+
+    #[test]
+    fn write_to_file_invalid_extension() {
+        let path = std::env::temp_dir().join("shpc_write_to_file_invalid_extension.bmp");
+        let image = HdrImage::new(1, 1, [0.0; 3]);
+        let error = image.write_to_file(&path).unwrap_err();
+        assert_eq!(ErrorKind::InvalidInput, error.kind());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn write_hdr_flat() {
+        let image = HdrImage {
+            width: 2,
+            height: 1,
+            data: vec![[1.0, 0.5, 0.0], [0.0, 0.0, 0.0]],
+        };
+        let mut bytes = Vec::new();
+        image.write_hdr(&mut bytes).unwrap();
+
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n";
+        assert_eq!(&header[..], &bytes[..header.len()]);
+        assert_eq!(&[128, 64, 0, 129, 0, 0, 0, 0], &bytes[header.len()..]);
+    }
+
+    #[test]
+    fn write_hdr_run_length_encoded() {
+        let width = 300;
+        let image = HdrImage {
+            width,
+            height: 2,
+            data: (0..width * 2).map(|i| [i as f32, 1.0, 0.0]).collect(),
+        };
+        let mut bytes = Vec::new();
+        image.write_hdr(&mut bytes).unwrap();
+
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 300\n";
+        assert_eq!(&header[..], &bytes[..header.len()]);
+
+        // Each scanline has 4 bytes of header and 3 runs for each component.
+        let scanline_size = 4 + 4 * (3 + width as usize);
+        assert_eq!(header.len() + scanline_size * 2, bytes.len());
+
+        let start = header.len() + scanline_size;
+        let pixels = decode_hdr_scanline(&bytes[start..], width as usize);
+        assert_eq!(rgbe([300.0, 1.0, 0.0]), pixels[0]);
+        assert_eq!(rgbe([599.0, 1.0, 0.0]), pixels[299]);
+    }
+
+    #[test]
+    fn write_png_header() {
+        let image = HdrImage::new(3, 2, [1.0, 0.0, 0.0]);
+        let mut bytes = Vec::new();
+        image.write_png(&mut bytes).unwrap();
+        assert_eq!(b"\x89PNG\r\n\x1a\n", &bytes[..8]);
+
+        let decoder = png::Decoder::new(bytes.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0u8; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((3, 2), (info.width, info.height));
+        assert_eq!(&[188, 0, 0], &pixels[..3]);
+    }
+}
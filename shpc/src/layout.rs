@@ -0,0 +1,418 @@
+//! Annotated byte layouts of SHAN files for reverse engineering.
+//!
+//! The layout is found by reading the offsets and counts directly from the bytes,
+//! so files with unexpected offsets can still be inspected.
+//! Bytes not covered by any field are reported as padding if they are all zero
+//! and as unknown otherwise, so unexplained data stands out.
+use std::fmt::Display;
+use std::io::{Error, ErrorKind, Write};
+
+/// The type of data for a [LayoutRange].
+#[derive(Debug, Clone, PartialEq)]
+pub enum RangeKind {
+    /// A field with a readable value.
+    Field(String),
+    /// A grid with an element count.
+    Grid(usize),
+    /// Zeros not covered by any field.
+    Padding,
+    /// Nonzero bytes not covered by any field.
+    Unknown,
+}
+
+impl Display for RangeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RangeKind::Field(value) => write!(f, "{}", value),
+            RangeKind::Grid(count) => write!(f, "[{} elements]", count),
+            RangeKind::Padding => write!(f, "padding"),
+            RangeKind::Unknown => write!(f, "UNKNOWN"),
+        }
+    }
+}
+
+/// A range of bytes in a file and the field it maps to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayoutRange {
+    pub start: usize,
+    pub end: usize,
+    /// The path to the field like `tpcbs[0].header.unk5` or empty for gaps.
+    pub name: String,
+    pub kind: RangeKind,
+}
+
+/// Finds the byte ranges for every field in the SHAN file `bytes`
+/// and the gaps between them sorted by starting offset.
+/// Returns an error if a field or offset extends past the end of `bytes`.
+pub fn layout(bytes: &[u8]) -> std::io::Result<Vec<LayoutRange>> {
+    let mut reader = LayoutReader {
+        bytes,
+        ranges: Vec::new(),
+    };
+
+    let magic = reader.string(0, 4, "magic")?;
+    if magic != b"SHAN" {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Expected magic SHAN but found {:?}", magic),
+        ));
+    }
+    reader.u32(4, "unk1")?;
+    let tpcb_count = reader.u32(8, "tpcb_count")? as usize;
+    reader.u32(12, "unk3")?;
+    let name_length = reader.u32(16, "name.length")? as usize;
+    reader.string(20, name_length, "name.bytes")?;
+
+    for i in 0..tpcb_count {
+        reader.u32(128 + i * 4, &format!("tpcb_starting_frames[{}]", i))?;
+    }
+    let pointers_start = 128 + tpcb_count * 4;
+    for i in 0..tpcb_count {
+        let offset = reader.u32(pointers_start + i * 4, &format!("tpcbs[{}]", i))? as usize;
+        if offset != 0 {
+            reader.tpcb(offset, i)?;
+        }
+    }
+
+    let mut ranges = reader.ranges;
+    ranges.sort_by_key(|r| (r.start, r.end));
+
+    // Fill in any bytes not covered by a field.
+    let mut result = Vec::new();
+    let mut position = 0;
+    for range in ranges {
+        if range.start > position {
+            result.push(gap(bytes, position, range.start));
+        }
+        position = position.max(range.end);
+        result.push(range);
+    }
+    if bytes.len() > position {
+        result.push(gap(bytes, position, bytes.len()));
+    }
+    Ok(result)
+}
+
+/// Writes each range in `ranges` with its offsets, name, value, and bytes from `bytes` in hex.
+/// Ranges longer than `max_lines` lines of 16 bytes are truncated.
+pub fn write_layout<W: Write>(
+    writer: &mut W,
+    bytes: &[u8],
+    ranges: &[LayoutRange],
+    max_lines: usize,
+) -> std::io::Result<()> {
+    for range in ranges {
+        writeln!(
+            writer,
+            "{:08X}..{:08X} ({} bytes) {} {}",
+            range.start,
+            range.end,
+            range.end - range.start,
+            range.name,
+            range.kind
+        )?;
+
+        let data = &bytes[range.start..range.end];
+        for (i, line) in data.chunks(16).enumerate() {
+            if i == max_lines {
+                writeln!(writer, "    ... {} more bytes", data.len() - i * 16)?;
+                break;
+            }
+            let hex: Vec<_> = line.iter().map(|b| format!("{:02X}", b)).collect();
+            writeln!(
+                writer,
+                "    {:08X}: {}",
+                range.start + i * 16,
+                hex.join(" ")
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn gap(bytes: &[u8], start: usize, end: usize) -> LayoutRange {
+    let kind = if bytes[start..end].iter().all(|b| *b == 0) {
+        RangeKind::Padding
+    } else {
+        RangeKind::Unknown
+    };
+    LayoutRange {
+        start,
+        end,
+        name: String::new(),
+        kind,
+    }
+}
+
+struct LayoutReader<'a> {
+    bytes: &'a [u8],
+    ranges: Vec<LayoutRange>,
+}
+
+impl<'a> LayoutReader<'a> {
+    fn bytes(&mut self, start: usize, size: usize, name: &str) -> std::io::Result<&'a [u8]> {
+        let bytes = start
+            .checked_add(size)
+            .and_then(|end| self.bytes.get(start..end))
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "{} at {:#X} with size {} extends past the end of the file",
+                        name, start, size
+                    ),
+                )
+            })?;
+        self.ranges.push(LayoutRange {
+            start,
+            end: start + size,
+            name: name.to_string(),
+            kind: RangeKind::Field(format!("{:?}", bytes)),
+        });
+        Ok(bytes)
+    }
+
+    fn set_value(&mut self, value: String) {
+        if let Some(range) = self.ranges.last_mut() {
+            range.kind = RangeKind::Field(value);
+        }
+    }
+
+    fn string(&mut self, start: usize, size: usize, name: &str) -> std::io::Result<&'a [u8]> {
+        let bytes = self.bytes(start, size, name)?;
+        self.set_value(format!("{:?}", String::from_utf8_lossy(bytes)));
+        Ok(bytes)
+    }
+
+    fn u16(&mut self, start: usize, name: &str) -> std::io::Result<u16> {
+        let value = u16::from_le_bytes(self.bytes(start, 2, name)?.try_into().unwrap());
+        self.set_value(value.to_string());
+        Ok(value)
+    }
+
+    fn u32(&mut self, start: usize, name: &str) -> std::io::Result<u32> {
+        let value = u32::from_le_bytes(self.bytes(start, 4, name)?.try_into().unwrap());
+        self.set_value(value.to_string());
+        Ok(value)
+    }
+
+    fn u32x3(&mut self, start: usize, name: &str) -> std::io::Result<[u32; 3]> {
+        let bytes = self.bytes(start, 12, name)?;
+        let value =
+            [0, 1, 2].map(|i| u32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap()));
+        self.set_value(format!("{:?}", value));
+        Ok(value)
+    }
+
+    fn f32(&mut self, start: usize, name: &str) -> std::io::Result<f32> {
+        let value = f32::from_le_bytes(self.bytes(start, 4, name)?.try_into().unwrap());
+        self.set_value(value.to_string());
+        Ok(value)
+    }
+
+    fn f32x3(&mut self, start: usize, name: &str) -> std::io::Result<[f32; 3]> {
+        let bytes = self.bytes(start, 12, name)?;
+        let value =
+            [0, 1, 2].map(|i| f32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap()));
+        self.set_value(format!("{:?}", value));
+        Ok(value)
+    }
+
+    fn grid(
+        &mut self,
+        start: usize,
+        count: usize,
+        element_size: usize,
+        name: &str,
+    ) -> std::io::Result<()> {
+        self.bytes(start, count * element_size, name)?;
+        self.set_value(String::new());
+        if let Some(range) = self.ranges.last_mut() {
+            range.kind = RangeKind::Grid(count);
+        }
+        Ok(())
+    }
+
+    fn tpcb(&mut self, start: usize, index: usize) -> std::io::Result<()> {
+        let name = |field: &str| format!("tpcbs[{}].{}", index, field);
+
+        self.string(start, 4, &name("magic"))?;
+        // Grid offsets are relative to the start of the TPCB.
+        let offset1 = self.u32(start + 4, &name("offset1"))? as usize;
+        let offset2 = self.u32(start + 8, &name("offset2"))? as usize;
+        let offset3 = self.u32(start + 12, &name("offset3"))? as usize;
+
+        let header = start + 16;
+        self.u16(header, &name("header.unk1_1"))?;
+        self.u16(header + 2, &name("header.unk1_2"))?;
+        self.u32x3(header + 4, &name("header.grid_cell_count_xyz"))?;
+        self.f32x3(header + 16, &name("header.grid_spacing_xyz"))?;
+        self.f32x3(header + 28, &name("header.grid_dimensions_xyz"))?;
+        self.f32x3(header + 40, &name("header.grid_range_min_xyz"))?;
+        self.f32x3(header + 52, &name("header.grid_range_max_xyz"))?;
+        self.u32(header + 64, &name("header.unk4"))?;
+        self.f32(header + 68, &name("header.unk5"))?;
+        self.f32(header + 72, &name("header.unk6"))?;
+        let count = self.u32(header + 76, &name("header.grid_cell_count"))? as usize;
+
+        for (offset, element_size, field) in [
+            (offset1, 2, "grid_indices"),
+            (offset2, 12, "grid_sh_coefficients"),
+            (offset3, 12, "grid_unk_values"),
+        ] {
+            if offset > 0 {
+                self.grid(start + offset, count, element_size, &name(field))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shan_bytes() -> Vec<u8> {
+        // A single TPCB with 2 cells and no unk values.
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"SHAN");
+        for value in [0u32, 1, 0, 5] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(b"chara");
+        bytes.resize(128, 0);
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&144u32.to_le_bytes());
+        bytes.resize(144, 0);
+
+        bytes.extend_from_slice(b"TPCB");
+        for value in [96u32, 100, 0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&3u16.to_le_bytes());
+        for value in [2u32, 1, 1] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [
+            1.0f32, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0,
+        ] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&12u32.to_le_bytes());
+        bytes.extend_from_slice(&(-1.0f32).to_le_bytes());
+        bytes.extend_from_slice(&0.01f32.to_le_bytes());
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        for value in [0u16, 1] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[128; 24]);
+        bytes
+    }
+
+    fn find<'a>(ranges: &'a [LayoutRange], name: &str) -> &'a LayoutRange {
+        ranges.iter().find(|r| r.name == name).unwrap()
+    }
+
+    #[test]
+    fn layout_covers_file() {
+        let bytes = shan_bytes();
+        let ranges = layout(&bytes).unwrap();
+
+        // The ranges should be contiguous without overlaps.
+        assert_eq!(0, ranges[0].start);
+        assert_eq!(bytes.len(), ranges.last().unwrap().end);
+        for pair in ranges.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start, "{:?}", pair);
+        }
+        assert!(ranges.iter().all(|r| r.kind != RangeKind::Unknown));
+    }
+
+    #[test]
+    fn layout_fields() {
+        let bytes = shan_bytes();
+        let ranges = layout(&bytes).unwrap();
+
+        let name = find(&ranges, "name.bytes");
+        assert_eq!((20, 25), (name.start, name.end));
+        assert_eq!(RangeKind::Field("\"chara\"".to_string()), name.kind);
+
+        // The name is padded to 128 bytes.
+        let padding = ranges.iter().position(|r| r.name == "name.bytes").unwrap() + 1;
+        assert_eq!(
+            (25, 128, RangeKind::Padding),
+            (
+                ranges[padding].start,
+                ranges[padding].end,
+                ranges[padding].kind.clone()
+            )
+        );
+
+        assert_eq!(
+            RangeKind::Field("[2, 1, 1]".to_string()),
+            find(&ranges, "tpcbs[0].header.grid_cell_count_xyz").kind
+        );
+        assert_eq!(
+            RangeKind::Field("-1".to_string()),
+            find(&ranges, "tpcbs[0].header.unk5").kind
+        );
+
+        assert_eq!(
+            RangeKind::Field("144".to_string()),
+            find(&ranges, "tpcbs[0]").kind
+        );
+        let tpcb = 144;
+        let indices = find(&ranges, "tpcbs[0].grid_indices");
+        assert_eq!((tpcb + 96, tpcb + 100), (indices.start, indices.end));
+        assert_eq!(RangeKind::Grid(2), indices.kind);
+        let coefficients = find(&ranges, "tpcbs[0].grid_sh_coefficients");
+        assert_eq!(
+            (tpcb + 100, tpcb + 124),
+            (coefficients.start, coefficients.end)
+        );
+    }
+
+    #[test]
+    fn layout_unknown_bytes() {
+        let mut bytes = shan_bytes();
+        bytes[100] = 1;
+        bytes.extend_from_slice(&[0, 0, 0, 0]);
+
+        let ranges = layout(&bytes).unwrap();
+        let unknown = ranges
+            .iter()
+            .find(|r| r.kind == RangeKind::Unknown)
+            .unwrap();
+        assert_eq!((25, 128), (unknown.start, unknown.end));
+        assert_eq!(RangeKind::Padding, ranges.last().unwrap().kind);
+        assert_eq!(bytes.len(), ranges.last().unwrap().end);
+    }
+
+    #[test]
+    fn layout_truncated() {
+        let bytes = shan_bytes();
+        let result = layout(&bytes[..bytes.len() - 1]);
+        assert_eq!(ErrorKind::InvalidData, result.unwrap_err().kind());
+
+        let result = layout(b"TPCB");
+        assert_eq!(ErrorKind::InvalidData, result.unwrap_err().kind());
+    }
+
+    #[test]
+    fn write_layout_truncates_lines() {
+        let bytes = [0u8; 40];
+        let ranges = vec![LayoutRange {
+            start: 0,
+            end: 40,
+            name: String::new(),
+            kind: RangeKind::Padding,
+        }];
+        let mut writer = Vec::new();
+        write_layout(&mut writer, &bytes, &ranges, 1).unwrap();
+        let text = String::from_utf8(writer).unwrap();
+        assert_eq!(
+            "00000000..00000028 (40 bytes)  padding\n    00000000: 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00\n    ... 24 more bytes\n",
+            text
+        );
+    }
+}
@@ -1,8 +1,28 @@
-use shan::{Grid, Shan, Tpcb, TpcbHeader};
+use std::collections::HashMap;
+
+use shan::{CompressedShCoefficients, Grid, Shan, Tpcb, TpcbHeader};
 use ssbh_lib::Ptr32;
 
+pub mod ambient_cube;
+pub mod anim;
+pub mod color;
+pub mod csv;
+pub mod diff;
+mod filter;
+#[cfg(feature = "gltf")]
+pub mod gltf;
+mod grid;
+pub mod image;
+pub mod layout;
+pub mod light;
+pub mod quantization;
+pub mod render;
+pub mod repair;
+pub mod round_trip;
 pub mod sh;
 pub mod shan;
+pub mod stats;
+pub mod texture;
 
 // TODO: Create a higher level API for applications to use.
 #[derive(Debug, Clone, PartialEq)]
@@ -36,116 +56,320 @@ pub struct GridCoefficients {
     pub coefficients: Vec<[[f32; 4]; 3]>,
 }
 
-impl GridCoefficients {
-    // TODO: Add trilinear interpolation?
-    pub fn get(&self, x: usize, y: usize, z: usize) -> [f32; 3] {
-        [0.0; 3]
-    }
+/// Errors while converting to or from the low level [Shan] representation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConvertError {
+    /// The pointer for the TPCB at `tpcb_index` is null.
+    MissingTpcb { tpcb_index: usize },
+    /// The `grid_indices` value for `cell` is not a valid index into `grid_sh_coefficients`.
+    InvalidGridIndex {
+        cell: usize,
+        index: u16,
+        record_count: usize,
+    },
+    /// The `grid_indices` has `index_count` indices but `grid_sh_coefficients` is null or empty.
+    MissingCoefficients { index_count: usize },
+    /// The grid has more coefficient records than can be referenced by the `u16` indices.
+    TooManyRecords { record_count: usize },
 }
 
-impl From<&Shan> for ShanFile {
-    fn from(shan: &Shan) -> Self {
-        // TODO: Avoid unwrap.
-        Self {
-            name: shan.name.to_string_lossy(),
-            tpcbs: shan
-                .tpcbs
-                .iter()
-                .zip(shan.tpcb_starting_frames.iter())
-                .map(|(tpcb, starting_frame)| TpcbData {
-                    starting_frame: *starting_frame,
-                    coefficients: tpcb.as_ref().unwrap().into(),
-                })
-                .collect(),
+impl std::fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConvertError::MissingTpcb { tpcb_index } => {
+                write!(f, "TPCB {} has a null offset", tpcb_index)
+            }
+            ConvertError::InvalidGridIndex {
+                cell,
+                index,
+                record_count,
+            } => write!(
+                f,
+                "Grid index {} for cell {} is out of range for {} coefficient records",
+                index, cell, record_count
+            ),
+            ConvertError::MissingCoefficients { index_count } => write!(
+                f,
+                "Missing coefficient records for {} grid indices",
+                index_count
+            ),
+            ConvertError::TooManyRecords { record_count } => write!(
+                f,
+                "{} coefficient records exceeds the maximum of {}",
+                record_count,
+                u16::MAX as usize + 1
+            ),
         }
     }
 }
 
-impl From<&ShanFile> for Shan {
-    fn from(shan: &ShanFile) -> Self {
-        Self {
-            unk1: shan
+impl std::error::Error for ConvertError {}
+
+/// The layout of the index grid when converting [GridCoefficients] to a [Tpcb].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GridIndexMode {
+    /// Each cell has its own coefficients with indices `0..grid_cell_count`.
+    #[default]
+    Identity,
+    /// Cells with identical compressed coefficients share a single coefficient record.
+    /// This allows grids with more than 65536 cells if there are at most 65536 unique records.
+    ///
+    /// The header only stores a single count for all grids,
+    /// so the unused records at the end are filled with zeros.
+    /// This only rewrites the indices and does not reduce the file size.
+    // TODO: Test if the game supports non identity indices.
+    Deduplicate,
+}
+
+impl ShanFile {
+    /// Converts to the low level representation using `mode` for the index grid of each TPCB.
+    pub fn to_shan(&self, mode: GridIndexMode) -> Result<Shan, ConvertError> {
+        Ok(Shan {
+            unk1: self
                 .tpcbs
                 .iter()
                 .map(|t| t.starting_frame)
                 .max()
                 .unwrap_or_default(),
-            tpcb_count: shan.tpcbs.len() as u32,
+            tpcb_count: self.tpcbs.len() as u32,
             unk3: 0,
-            name: shan.name.clone().into(),
-            tpcb_starting_frames: shan.tpcbs.iter().map(|t| t.starting_frame).collect(),
-            tpcbs: shan
+            name: self.name.clone().into(),
+            tpcb_starting_frames: self.tpcbs.iter().map(|t| t.starting_frame).collect(),
+            tpcbs: self
                 .tpcbs
                 .iter()
-                .map(|tpcb| Ptr32::new((&tpcb.coefficients).into()))
-                .collect(),
-        }
+                .map(|tpcb| tpcb.coefficients.to_tpcb(mode).map(Ptr32::new))
+                .collect::<Result<_, _>>()?,
+        })
     }
 }
 
-// TODO: Also implement for non references.
-impl From<&Tpcb> for GridCoefficients {
-    fn from(t: &Tpcb) -> Self {
-        Self {
-            grid_cell_count_xyz: t.inner.header.grid_cell_count_xyz,
-            grid_range_min_xyz: t.inner.header.grid_range_min_xyz,
-            grid_range_max_xyz: t.inner.header.grid_range_max_xyz,
-            unk5: t.inner.header.unk5,
-            unk6: t.inner.header.unk6,
-            coefficients: t
-                .inner
-                .grid_sh_coefficients
-                .0
-                .as_ref()
-                .unwrap()
-                .iter()
-                .map(|c| {
-                    [
-                        sh::decompress_coefficients(t.inner.header.unk5, t.inner.header.unk6, c.r),
-                        sh::decompress_coefficients(t.inner.header.unk5, t.inner.header.unk6, c.g),
-                        sh::decompress_coefficients(t.inner.header.unk5, t.inner.header.unk6, c.b),
-                    ]
-                })
-                .collect(),
-        }
+impl GridCoefficients {
+    /// Updates `unk5` and `unk6` to fit the range of `coefficients`.
+    /// This should be called after modifying the coefficients to avoid clipping when compressing.
+    pub fn recalculate_unk5_unk6(&mut self) {
+        let (unk5, unk6) =
+            sh::compression_params(self.coefficients.iter().flat_map(|c| c.iter().copied()));
+        self.unk5 = unk5;
+        self.unk6 = unk6;
     }
-}
 
-impl From<&GridCoefficients> for Tpcb {
-    fn from(g: &GridCoefficients) -> Self {
+    /// Converts to the low level representation using `mode` for the index grid.
+    pub fn to_tpcb(&self, mode: GridIndexMode) -> Result<Tpcb, ConvertError> {
         // TODO: Is there a cleaner way of calculating this?
         let mut grid_dimensions_xyz = [0.0; 3];
         for i in 0..3 {
-            grid_dimensions_xyz[i] = g.grid_range_max_xyz[i] - g.grid_range_min_xyz[i];
+            grid_dimensions_xyz[i] = self.grid_range_max_xyz[i] - self.grid_range_min_xyz[i];
         }
 
-        let mut grid_spacing_xyz = [1.0; 3];
-        for i in 0..3 {
-            if g.grid_cell_count_xyz[i] > 1 {
-                grid_spacing_xyz[i] =
-                    grid_dimensions_xyz[i] / (g.grid_cell_count_xyz[i] as f32 - 1.0);
-            }
-        }
+        let grid_spacing_xyz = self.spacing();
+
+        let compressed: Vec<_> = self
+            .coefficients
+            .iter()
+            .map(|c| CompressedShCoefficients {
+                r: sh::compress_coefficients(self.unk5, self.unk6, c[0]),
+                g: sh::compress_coefficients(self.unk5, self.unk6, c[1]),
+                b: sh::compress_coefficients(self.unk5, self.unk6, c[2]),
+            })
+            .collect();
 
-        Self {
+        let (grid_indices, grid_sh_coefficients) = match mode {
+            GridIndexMode::Identity => (record_indices(compressed.len())?, compressed),
+            GridIndexMode::Deduplicate => deduplicate(compressed)?,
+        };
+
+        Ok(Tpcb {
             inner: shan::TpcbInner {
                 header: TpcbHeader {
                     unk1_1: 1,
                     unk1_2: 35, // TODO: How to fill in this value?
-                    grid_cell_count_xyz: g.grid_cell_count_xyz,
+                    grid_cell_count_xyz: self.grid_cell_count_xyz,
                     grid_spacing_xyz,
                     grid_dimensions_xyz,
-                    grid_range_min_xyz: g.grid_range_min_xyz,
-                    grid_range_max_xyz: g.grid_range_max_xyz,
+                    grid_range_min_xyz: self.grid_range_min_xyz,
+                    grid_range_max_xyz: self.grid_range_max_xyz,
                     unk4: 12,
-                    unk5: g.unk5,
-                    unk6: g.unk6,
-                    grid_cell_count: g.coefficients.len() as u32,
+                    unk5: self.unk5,
+                    unk6: self.unk6,
+                    grid_cell_count: self.coefficients.len() as u32,
                 },
-                grid_indices: Grid(Some((0..g.coefficients.len() as u16).collect())),
-                grid_sh_coefficients: Grid(None),
+                grid_indices: Grid(Some(grid_indices)),
+                grid_sh_coefficients: Grid(Some(grid_sh_coefficients)),
                 grid_unk_values: Grid(None),
             },
+        })
+    }
+}
+
+impl TryFrom<&Shan> for ShanFile {
+    type Error = ConvertError;
+
+    fn try_from(shan: &Shan) -> Result<Self, Self::Error> {
+        Ok(Self {
+            name: shan.name.to_string_lossy(),
+            tpcbs: shan
+                .tpcbs
+                .iter()
+                .zip(shan.tpcb_starting_frames.iter())
+                .enumerate()
+                .map(|(i, (tpcb, starting_frame))| {
+                    let tpcb = tpcb
+                        .as_ref()
+                        .ok_or(ConvertError::MissingTpcb { tpcb_index: i })?;
+                    Ok(TpcbData {
+                        starting_frame: *starting_frame,
+                        coefficients: tpcb.try_into()?,
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl TryFrom<&ShanFile> for Shan {
+    type Error = ConvertError;
+
+    fn try_from(shan: &ShanFile) -> Result<Self, Self::Error> {
+        shan.to_shan(GridIndexMode::Identity)
+    }
+}
+
+// TODO: Also implement for non references.
+impl TryFrom<&Tpcb> for GridCoefficients {
+    type Error = ConvertError;
+
+    fn try_from(t: &Tpcb) -> Result<Self, Self::Error> {
+        let unk5 = t.inner.header.unk5;
+        let unk6 = t.inner.header.unk6;
+        let decompress = |c: &CompressedShCoefficients| {
+            [
+                sh::decompress_coefficients(unk5, unk6, c.r),
+                sh::decompress_coefficients(unk5, unk6, c.g),
+                sh::decompress_coefficients(unk5, unk6, c.b),
+            ]
+        };
+
+        let records = t
+            .inner
+            .grid_sh_coefficients
+            .0
+            .as_deref()
+            .unwrap_or_default();
+
+        // Each cell's coefficients are found through the index grid.
+        let coefficients = match &t.inner.grid_indices.0 {
+            Some(indices) if !indices.is_empty() && records.is_empty() => {
+                return Err(ConvertError::MissingCoefficients {
+                    index_count: indices.len(),
+                })
+            }
+            Some(indices) => indices
+                .iter()
+                .enumerate()
+                .map(|(cell, index)| {
+                    records.get(*index as usize).map(decompress).ok_or(
+                        ConvertError::InvalidGridIndex {
+                            cell,
+                            index: *index,
+                            record_count: records.len(),
+                        },
+                    )
+                })
+                .collect::<Result<_, _>>()?,
+            None => records.iter().map(decompress).collect(),
+        };
+
+        Ok(Self {
+            grid_cell_count_xyz: t.inner.header.grid_cell_count_xyz,
+            grid_range_min_xyz: t.inner.header.grid_range_min_xyz,
+            grid_range_max_xyz: t.inner.header.grid_range_max_xyz,
+            unk5,
+            unk6,
+            coefficients,
+        })
+    }
+}
+
+impl TryFrom<&GridCoefficients> for Tpcb {
+    type Error = ConvertError;
+
+    fn try_from(g: &GridCoefficients) -> Result<Self, Self::Error> {
+        g.to_tpcb(GridIndexMode::Identity)
+    }
+}
+
+// The indices 0..record_count if each record can be indexed with a u16.
+fn record_indices(record_count: usize) -> Result<Vec<u16>, ConvertError> {
+    if record_count > u16::MAX as usize + 1 {
+        return Err(ConvertError::TooManyRecords { record_count });
+    }
+    Ok((0..record_count).map(|i| i as u16).collect())
+}
+
+fn deduplicate(
+    coefficients: Vec<CompressedShCoefficients>,
+) -> Result<(Vec<u16>, Vec<CompressedShCoefficients>), ConvertError> {
+    let mut unique = Vec::new();
+    let mut unique_indices = HashMap::new();
+    let indices = coefficients
+        .iter()
+        .map(|c| {
+            *unique_indices.entry((c.r, c.g, c.b)).or_insert_with(|| {
+                unique.push(c.clone());
+                unique.len() - 1
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if unique.len() > u16::MAX as usize + 1 {
+        return Err(ConvertError::TooManyRecords {
+            record_count: unique.len(),
+        });
+    }
+    let indices = indices.into_iter().map(|i| i as u16).collect();
+
+    // All grids have the same length since the header only stores a single count.
+    unique.resize(
+        coefficients.len(),
+        CompressedShCoefficients {
+            r: [0; 4],
+            g: [0; 4],
+            b: [0; 4],
+        },
+    );
+    Ok((indices, unique))
+}
+
+// Fixtures shared by the unit tests in each module.
+#[cfg(test)]
+mod test_fixtures {
+    use crate::{GridCoefficients, ShanFile, TpcbData};
+
+    /// A grid with a spacing of `1.0` starting from the origin and `unk5` and `unk6` of `0.0`.
+    /// Use struct update syntax for tests that depend on the other fields.
+    pub fn grid(counts: [u32; 3], coefficients: Vec<[[f32; 4]; 3]>) -> GridCoefficients {
+        GridCoefficients {
+            grid_cell_count_xyz: counts,
+            grid_range_min_xyz: [0.0; 3],
+            grid_range_max_xyz: counts.map(|c| c.saturating_sub(1) as f32),
+            unk5: 0.0,
+            unk6: 0.0,
+            coefficients,
+        }
+    }
+
+    /// A file named `"chara"` with a TPCB for each starting frame and grid.
+    pub fn shan_file(tpcbs: Vec<(u32, GridCoefficients)>) -> ShanFile {
+        ShanFile {
+            name: "chara".to_string(),
+            tpcbs: tpcbs
+                .into_iter()
+                .map(|(starting_frame, coefficients)| TpcbData {
+                    starting_frame,
+                    coefficients,
+                })
+                .collect(),
         }
     }
 }
@@ -157,7 +381,7 @@ mod tests {
     use ssbh_lib::Ptr32;
 
     use super::*;
-    use crate::shan::{Grid, Shan, TpcbHeader, TpcbInner};
+    use crate::shan::{CompressedShCoefficients, Grid, Shan, TpcbHeader, TpcbInner};
 
     #[test]
     fn shan_file_xeno_gaur() {
@@ -245,10 +469,10 @@ mod tests {
             ],
         };
 
-        let new_shan_file = ShanFile::from(&shan);
+        let new_shan_file = ShanFile::try_from(&shan).unwrap();
         assert_eq!(new_shan_file, shan_file);
 
-        let new_shan = Shan::from(&shan_file);
+        let new_shan = Shan::try_from(&shan_file).unwrap();
         assert_eq!(new_shan.unk1, shan.unk1);
         assert_eq!(new_shan.tpcb_count, shan.tpcb_count);
         assert_eq!(new_shan.unk3, shan.unk3);
@@ -289,7 +513,14 @@ mod tests {
                     181, 182, 183, 184, 185, 186, 187, 188, 189, 190, 191, 192, 193, 194, 195, 196,
                     197, 198, 199, 200, 201, 202, 203, 204, 205, 206, 207, 208, 209,
                 ])),
-                grid_sh_coefficients: Grid(None),
+                grid_sh_coefficients: Grid(Some(vec![
+                    CompressedShCoefficients {
+                        r: [0; 4],
+                        g: [0; 4],
+                        b: [0; 4],
+                    };
+                    210
+                ])),
                 grid_unk_values: Grid(None),
             },
         };
@@ -304,13 +535,13 @@ mod tests {
         };
 
         // Test GridCoefficients -> Tpcb
-        let new_tpcb = Tpcb::from(&grid);
+        let new_tpcb = Tpcb::try_from(&grid).unwrap();
         assert_eq!(new_tpcb.inner.header, tpcb.inner.header);
         assert_eq!(new_tpcb.inner.grid_indices.0, tpcb.inner.grid_indices.0);
         // TODO: Test coefficient lengths?
 
         // Test Tpcb -> GridCoefficients
-        let new_grid = GridCoefficients::from(&tpcb);
+        let new_grid = GridCoefficients::try_from(&tpcb).unwrap();
         assert_eq!(new_grid.grid_cell_count_xyz, grid.grid_cell_count_xyz);
         assert_eq!(new_grid.grid_range_min_xyz, grid.grid_range_min_xyz);
         assert_eq!(new_grid.grid_range_min_xyz, grid.grid_range_min_xyz);
@@ -338,7 +569,14 @@ mod tests {
                 grid_indices: Grid(Some(vec![
                     0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20,
                 ])),
-                grid_sh_coefficients: Grid(None),
+                grid_sh_coefficients: Grid(Some(vec![
+                    CompressedShCoefficients {
+                        r: [0; 4],
+                        g: [0; 4],
+                        b: [0; 4],
+                    };
+                    21
+                ])),
                 grid_unk_values: Grid(None),
             },
         };
@@ -353,16 +591,199 @@ mod tests {
         };
 
         // Test GridCoefficients -> Tpcb
-        let new_tpcb = Tpcb::from(&grid);
+        let new_tpcb = Tpcb::try_from(&grid).unwrap();
         assert_eq!(new_tpcb.inner.header, tpcb.inner.header);
         assert_eq!(new_tpcb.inner.grid_indices.0, tpcb.inner.grid_indices.0);
         // TODO: Test coefficient lengths?
 
         // Test Tpcb -> GridCoefficients
-        let new_grid = GridCoefficients::from(&tpcb);
+        let new_grid = GridCoefficients::try_from(&tpcb).unwrap();
         assert_eq!(new_grid.grid_cell_count_xyz, grid.grid_cell_count_xyz);
         assert_eq!(new_grid.grid_range_min_xyz, grid.grid_range_min_xyz);
         assert_eq!(new_grid.grid_range_min_xyz, grid.grid_range_min_xyz);
         // TODO: Test coefficient lengths?
     }
+
+    #[test]
+    fn grid_coefficients_deduplicate() {
+        let a = [[0.0, 0.0, 0.0, 1.0]; 3];
+        let b = [[0.5, 0.0, 0.0, 2.0]; 3];
+        let mut grid = GridCoefficients {
+            grid_cell_count_xyz: [2, 2, 1],
+            grid_range_min_xyz: [0.0, 0.0, 0.0],
+            grid_range_max_xyz: [1.0, 1.0, 0.0],
+            unk5: 0.0,
+            unk6: 0.0,
+            coefficients: vec![a, b, a, a],
+        };
+        grid.recalculate_unk5_unk6();
+
+        let identity = grid.to_tpcb(GridIndexMode::Identity).unwrap();
+        assert_eq!(Some(vec![0, 1, 2, 3]), identity.inner.grid_indices.0);
+
+        let deduplicated = grid.to_tpcb(GridIndexMode::Deduplicate).unwrap();
+        assert_eq!(Some(vec![0, 1, 0, 0]), deduplicated.inner.grid_indices.0);
+
+        let records = deduplicated.inner.grid_sh_coefficients.0.as_ref().unwrap();
+        assert_eq!(4, records.len());
+        assert_eq!(
+            identity.inner.grid_sh_coefficients.0.as_ref().unwrap()[..2],
+            records[..2]
+        );
+        assert_eq!([0; 4], records[2].r);
+        assert_eq!([0; 4], records[3].r);
+
+        // Both modes should decompress to the same coefficients.
+        assert_eq!(
+            GridCoefficients::try_from(&identity).unwrap(),
+            GridCoefficients::try_from(&deduplicated).unwrap()
+        );
+    }
+
+    #[test]
+    fn grid_coefficients_too_many_records() {
+        let grid = GridCoefficients {
+            grid_cell_count_xyz: [65537, 1, 1],
+            grid_range_min_xyz: [0.0, 0.0, 0.0],
+            grid_range_max_xyz: [1.0, 0.0, 0.0],
+            unk5: 0.0,
+            unk6: 0.0,
+            coefficients: vec![[[0.0; 4]; 3]; 65537],
+        };
+        assert_eq!(
+            Err(ConvertError::TooManyRecords {
+                record_count: 65537
+            }),
+            grid.to_tpcb(GridIndexMode::Identity)
+        );
+
+        // Deduplicating allows more cells than records.
+        let tpcb = grid.to_tpcb(GridIndexMode::Deduplicate).unwrap();
+        assert_eq!(Some(vec![0; 65537]), tpcb.inner.grid_indices.0);
+    }
+
+    #[test]
+    fn deduplicate_too_many_records() {
+        let records = (0..65537u32)
+            .map(|i| {
+                let [a, b, c, d] = i.to_le_bytes();
+                CompressedShCoefficients {
+                    r: [a, b, c, d],
+                    g: [0; 4],
+                    b: [0; 4],
+                }
+            })
+            .collect();
+        assert_eq!(
+            Err(ConvertError::TooManyRecords {
+                record_count: 65537
+            }),
+            deduplicate(records)
+        );
+    }
+
+    fn tpcb_with_indices(grid_indices: Vec<u16>) -> Tpcb {
+        Tpcb {
+            inner: TpcbInner {
+                header: TpcbHeader {
+                    unk1_1: 1,
+                    unk1_2: 35,
+                    grid_cell_count_xyz: [3, 1, 1],
+                    grid_spacing_xyz: [1.0, 1.0, 1.0],
+                    grid_dimensions_xyz: [2.0, 0.0, 0.0],
+                    grid_range_min_xyz: [0.0, 0.0, 0.0],
+                    grid_range_max_xyz: [2.0, 0.0, 0.0],
+                    unk4: 12,
+                    unk5: 0.0,
+                    unk6: 1.0,
+                    grid_cell_count: 3,
+                },
+                grid_indices: Grid(Some(grid_indices)),
+                grid_sh_coefficients: Grid(Some(
+                    [0, 128, 255]
+                        .iter()
+                        .map(|v| CompressedShCoefficients {
+                            r: [*v, 0, 0, 0],
+                            g: [*v, 0, 0, 0],
+                            b: [*v, 0, 0, 0],
+                        })
+                        .collect(),
+                )),
+                grid_unk_values: Grid(None),
+            },
+        }
+    }
+
+    fn l0_values(grid: &GridCoefficients) -> Vec<f32> {
+        grid.coefficients.iter().map(|c| c[0][3]).collect()
+    }
+
+    fn expected_l0_values(records: &[usize]) -> Vec<f32> {
+        records
+            .iter()
+            .map(|i| sh::decompress_coefficients(0.0, 1.0, [[0, 128, 255][*i], 0, 0, 0])[3])
+            .collect()
+    }
+
+    #[test]
+    fn grid_coefficients_identity_indices() {
+        let grid = GridCoefficients::try_from(&tpcb_with_indices(vec![0, 1, 2])).unwrap();
+        assert_eq!(expected_l0_values(&[0, 1, 2]), l0_values(&grid));
+    }
+
+    #[test]
+    fn grid_coefficients_permuted_indices() {
+        let grid = GridCoefficients::try_from(&tpcb_with_indices(vec![2, 0, 1])).unwrap();
+        assert_eq!(expected_l0_values(&[2, 0, 1]), l0_values(&grid));
+    }
+
+    #[test]
+    fn grid_coefficients_repeated_indices() {
+        let grid = GridCoefficients::try_from(&tpcb_with_indices(vec![1, 1, 0])).unwrap();
+        assert_eq!(expected_l0_values(&[1, 1, 0]), l0_values(&grid));
+    }
+
+    #[test]
+    fn grid_coefficients_invalid_index() {
+        assert_eq!(
+            Err(ConvertError::InvalidGridIndex {
+                cell: 1,
+                index: 3,
+                record_count: 3
+            }),
+            GridCoefficients::try_from(&tpcb_with_indices(vec![0, 3, 1]))
+        );
+    }
+
+    #[test]
+    fn grid_coefficients_missing_coefficients() {
+        let mut tpcb = tpcb_with_indices(vec![0, 1, 2]);
+        tpcb.inner.grid_sh_coefficients = Grid(None);
+        assert_eq!(
+            Err(ConvertError::MissingCoefficients { index_count: 3 }),
+            GridCoefficients::try_from(&tpcb)
+        );
+
+        tpcb.inner.grid_sh_coefficients = Grid(Some(Vec::new()));
+        assert_eq!(
+            Err(ConvertError::MissingCoefficients { index_count: 3 }),
+            GridCoefficients::try_from(&tpcb)
+        );
+    }
+
+    #[test]
+    fn shan_file_null_tpcb() {
+        let shan = Shan {
+            unk1: 0,
+            tpcb_count: 1,
+            unk3: 0,
+            name: String::new().into(),
+            tpcb_starting_frames: vec![0],
+            tpcbs: vec![Ptr32::null()],
+        };
+        assert_eq!(
+            Err(ConvertError::MissingTpcb { tpcb_index: 0 }),
+            ShanFile::try_from(&shan)
+        );
+    }
 }
@@ -0,0 +1,263 @@
+//! Decomposing coefficients into an ambient color and a dominant directional light.
+//!
+//! A directional light with linear RGB `c` in the unit direction `d` has L0 and L1 irradiance
+//! `c * (1/4 + 1/2 * dot(d, n))`, which is the projection of the clamped cosine `c * max(dot(d, n), 0)`.
+//! The ambient color only contributes to L0.
+//! L1 components perpendicular to the dominant direction can't be represented,
+//! so the decomposition is lossy for probes with L1 pointing in different directions per channel.
+use crate::color::{luminance, LUMINANCE_WEIGHTS};
+use crate::grid::add_scaled;
+use crate::GridCoefficients;
+
+/// A directional light with an RGB color and intensity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DirectionalLight {
+    /// The unit direction pointing towards the light.
+    /// Surfaces with normals facing this direction receive the most light.
+    pub direction: [f32; 3],
+    /// The linear RGB color with a [luminance] of `1.0` or black if the intensity is zero.
+    pub color: [f32; 3],
+    /// The luminance of the light, so the light's RGB value is `color * intensity`.
+    pub intensity: f32,
+}
+
+impl DirectionalLight {
+    /// The linear RGB value of the light.
+    pub fn rgb(&self) -> [f32; 3] {
+        self.color.map(|c| c * self.intensity)
+    }
+}
+
+/// The ambient color and dominant light for a single probe.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightDecomposition {
+    /// The constant linear RGB irradiance from all directions.
+    pub ambient: [f32; 3],
+    pub light: DirectionalLight,
+}
+
+/// Decomposes the decompressed `coefficients` for a cell into ambient and directional light.
+/// The direction is the luminance weighted average L1 direction.
+pub fn decompose(coefficients: &[[f32; 4]; 3]) -> LightDecomposition {
+    let mut l1 = [0.0; 3];
+    for (c, weight) in coefficients.iter().zip(LUMINANCE_WEIGHTS) {
+        for (l1, value) in l1.iter_mut().zip(c) {
+            *l1 += value * weight;
+        }
+    }
+
+    let length = l1.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if length == 0.0 || !length.is_finite() {
+        // There is no directional component.
+        return LightDecomposition {
+            ambient: coefficients.map(|c| c[3]),
+            light: DirectionalLight {
+                direction: [0.0, 1.0, 0.0],
+                color: [0.0; 3],
+                intensity: 0.0,
+            },
+        };
+    }
+    let direction = l1.map(|v| v / length);
+
+    // Project each channel's L1 onto the shared direction.
+    let rgb = coefficients.map(|c| 2.0 * dot([c[0], c[1], c[2]], direction).max(0.0));
+    let intensity = luminance(rgb);
+    let color = if intensity > 0.0 {
+        rgb.map(|c| c / intensity)
+    } else {
+        [0.0; 3]
+    };
+
+    LightDecomposition {
+        ambient: [0, 1, 2].map(|i| coefficients[i][3] - rgb[i] / 4.0),
+        light: DirectionalLight {
+            direction,
+            color,
+            intensity,
+        },
+    }
+}
+
+/// Creates the coefficients for a cell from ambient and directional light.
+/// This is the inverse of [decompose].
+pub fn compose(decomposition: &LightDecomposition) -> [[f32; 4]; 3] {
+    let rgb = decomposition.light.rgb();
+    let [x, y, z] = decomposition.light.direction;
+    [0, 1, 2].map(|i| {
+        let l1 = rgb[i] / 2.0;
+        [
+            l1 * x,
+            l1 * y,
+            l1 * z,
+            decomposition.ambient[i] + rgb[i] / 4.0,
+        ]
+    })
+}
+
+impl GridCoefficients {
+    /// Decomposes the coefficients for each cell using [decompose].
+    pub fn decompose_lights(&self) -> Vec<LightDecomposition> {
+        self.coefficients.iter().map(decompose).collect()
+    }
+
+    /// Decomposes the average coefficients of all cells using [decompose].
+    /// Returns `None` if there are no cells.
+    pub fn average_light(&self) -> Option<LightDecomposition> {
+        if self.coefficients.is_empty() {
+            return None;
+        }
+
+        let mut average = [[0.0; 4]; 3];
+        let weight = 1.0 / self.coefficients.len() as f32;
+        for c in &self.coefficients {
+            add_scaled(&mut average, c, weight);
+        }
+        Some(decompose(&average))
+    }
+
+    /// Creates a grid with the coefficients for each cell from [compose].
+    /// The `unk5` and `unk6` values are calculated to fit the coefficients.
+    pub fn from_lights(
+        grid_cell_count_xyz: [u32; 3],
+        grid_range_min_xyz: [f32; 3],
+        grid_range_max_xyz: [f32; 3],
+        lights: &[LightDecomposition],
+    ) -> Self {
+        let mut grid = Self {
+            grid_cell_count_xyz,
+            grid_range_min_xyz,
+            grid_range_max_xyz,
+            unk5: 0.0,
+            unk6: 0.0,
+            coefficients: lights.iter().map(compose).collect(),
+        };
+        grid.recalculate_unk5_unk6();
+        grid
+    }
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sh;
+    use crate::test_fixtures::grid;
+
+    macro_rules! assert_almost_eq {
+        ($a:expr, $b:expr) => {
+            assert!(
+                $a.iter()
+                    .flatten()
+                    .zip($b.iter().flatten())
+                    .all(|(a, b): (&f32, &f32)| (a - b).abs() < 0.0001),
+                "{:?} != {:?}",
+                $a,
+                $b
+            );
+        };
+    }
+
+    #[test]
+    fn decompose_ambient_only() {
+        let coefficients = [[0.0, 0.0, 0.0, 0.5], [0.0, 0.0, 0.0, 0.25], [0.0; 4]];
+        let decomposition = decompose(&coefficients);
+        assert_eq!([0.5, 0.25, 0.0], decomposition.ambient);
+        assert_eq!(0.0, decomposition.light.intensity);
+        assert_eq!([0.0; 3], decomposition.light.rgb());
+        assert_eq!(coefficients, compose(&decomposition));
+    }
+
+    #[test]
+    fn decompose_white_light() {
+        // A white light from +Y with an ambient of 0.1.
+        let coefficients = [[0.0, 0.5, 0.0, 0.35]; 3];
+        let decomposition = decompose(&coefficients);
+        assert_almost_eq!([decomposition.ambient], [[0.1; 3]]);
+        assert_almost_eq!([decomposition.light.direction], [[0.0, 1.0, 0.0]]);
+        assert_almost_eq!([decomposition.light.color], [[1.0; 3]]);
+        assert!((decomposition.light.intensity - 1.0).abs() < 0.0001);
+
+        // The light faces the +Y direction.
+        let up = sh::irradiance(coefficients[0], [0.0, 1.0, 0.0]);
+        let down = sh::irradiance(coefficients[0], [0.0, -1.0, 0.0]);
+        assert!((up - 0.85).abs() < 0.0001);
+        assert!((down + 0.15).abs() < 0.0001);
+    }
+
+    #[test]
+    fn decompose_colored_light() {
+        let direction = [0.6, 0.0, -0.8];
+        let rgb = [1.0, 0.5, 0.25];
+        let intensity = luminance(rgb);
+        let decomposition = LightDecomposition {
+            ambient: [0.2, 0.1, 0.05],
+            light: DirectionalLight {
+                direction,
+                color: rgb.map(|c| c / intensity),
+                intensity,
+            },
+        };
+        let coefficients = compose(&decomposition);
+
+        let result = decompose(&coefficients);
+        assert_almost_eq!([result.ambient], [decomposition.ambient]);
+        assert_almost_eq!([result.light.direction], [direction]);
+        assert_almost_eq!([result.light.rgb()], [rgb]);
+        assert!((luminance(result.light.color) - 1.0).abs() < 0.0001);
+        assert_almost_eq!(compose(&result), coefficients);
+    }
+
+    #[test]
+    fn decompose_opposite_channels() {
+        // Red points along +X and blue points along -X.
+        // The dominant direction follows the brighter red channel.
+        let coefficients = [
+            [0.5, 0.0, 0.0, 1.0],
+            [0.0, 0.0, 0.0, 1.0],
+            [-0.5, 0.0, 0.0, 1.0],
+        ];
+        let decomposition = decompose(&coefficients);
+        assert_almost_eq!([decomposition.light.direction], [[1.0, 0.0, 0.0]]);
+        assert_almost_eq!([decomposition.light.rgb()], [[1.0, 0.0, 0.0]]);
+        assert_almost_eq!([decomposition.ambient], [[0.75, 1.0, 1.0]]);
+    }
+
+    #[test]
+    fn grid_lights_round_trip() {
+        let grid = grid(
+            [2, 1, 1],
+            vec![[[0.0, 0.5, 0.0, 0.35]; 3], [[0.0, 0.0, -0.2, 0.5]; 3]],
+        );
+
+        let lights = grid.decompose_lights();
+        assert_eq!(2, lights.len());
+
+        let new_grid = GridCoefficients::from_lights([2, 1, 1], [0.0; 3], [1.0, 0.0, 0.0], &lights);
+        for (a, b) in grid.coefficients.iter().zip(new_grid.coefficients.iter()) {
+            assert_almost_eq!(a, b);
+        }
+        assert_ne!(0.0, new_grid.unk6);
+    }
+
+    #[test]
+    fn average_light() {
+        let grid = grid(
+            [2, 1, 1],
+            vec![[[0.0, 1.0, 0.0, 0.5]; 3], [[0.0, 0.0, 0.0, 0.5]; 3]],
+        );
+        let light = grid.average_light().unwrap();
+        assert_almost_eq!([light.light.direction], [[0.0, 1.0, 0.0]]);
+        assert!((light.light.intensity - 1.0).abs() < 0.0001);
+        assert_almost_eq!([light.ambient], [[0.25; 3]]);
+
+        let empty = GridCoefficients {
+            coefficients: Vec::new(),
+            ..grid
+        };
+        assert_eq!(None, empty.average_light());
+    }
+}
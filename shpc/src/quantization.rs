@@ -0,0 +1,294 @@
+//! Measuring the error introduced by compressing coefficients to 8 bits.
+//!
+//! Coefficients are compressed and then decompressed again with the grid's `unk5` and `unk6`.
+//! Coefficients decompressed from an unmodified file should round trip without any error,
+//! so [GridCoefficients::refit_quantization_report] measures the error from re-encoding
+//! with the `unk5` and `unk6` from [GridCoefficients::recalculate_unk5_unk6] instead.
+use crate::{sh, GridCoefficients, ShanFile};
+
+/// Unit normals along the positive and negative X, Y, and Z axes.
+pub const AXIS_NORMALS: [[f32; 3]; 6] = [
+    [1.0, 0.0, 0.0],
+    [-1.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0],
+    [0.0, -1.0, 0.0],
+    [0.0, 0.0, 1.0],
+    [0.0, 0.0, -1.0],
+];
+
+/// The round trip error for a single grid cell.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellError {
+    /// The index into the grid coefficients.
+    pub index: usize,
+    /// The maximum absolute difference for any coefficient.
+    pub max_coefficient_error: f32,
+    /// The maximum absolute difference in irradiance for any channel and normal.
+    pub max_irradiance_error: f32,
+    /// `true` if any compressed value is 0 or 255.
+    /// This may indicate that the coefficients were clipped when compressing.
+    pub hits_limit: bool,
+}
+
+/// The round trip error for all cells in a grid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantizationReport {
+    pub cells: Vec<CellError>,
+    /// The maximum absolute difference for any coefficient in any cell.
+    pub max_coefficient_error: f32,
+    /// The mean absolute difference for all coefficients in all cells.
+    pub mean_coefficient_error: f32,
+    /// The maximum absolute difference in irradiance for any cell, channel, and normal.
+    pub max_irradiance_error: f32,
+    /// The root mean square difference in irradiance for all cells, channels, and normals.
+    pub rms_irradiance_error: f32,
+    /// The peak signal to noise ratio in decibels using the maximum absolute irradiance as the peak.
+    /// This is infinite if there is no error.
+    pub psnr: f32,
+    /// The number of cells with each compressed value indexed by `[channel][coefficient][value]`.
+    /// The coefficient index matches [GridCoefficients::coefficients] rather than the compressed byte order.
+    pub histograms: Box<[[[u32; 256]; 4]; 3]>,
+}
+
+impl QuantizationReport {
+    /// The cells with any compressed value of 0 or 255.
+    pub fn limit_cells(&self) -> impl Iterator<Item = &CellError> {
+        self.cells.iter().filter(|c| c.hits_limit)
+    }
+
+    /// The minimum and maximum compressed value and the number of distinct values
+    /// for the coefficient at `[channel][coefficient]`.
+    /// Returns `None` if there are no cells.
+    pub fn byte_usage(&self, channel: usize, coefficient: usize) -> Option<(u8, u8, usize)> {
+        let histogram = &self.histograms[channel][coefficient];
+        let mut used = (0..=255u8).filter(|v| histogram[*v as usize] > 0);
+        let min = used.next()?;
+        let (max, count) = used.fold((min, 1), |(_, count), v| (v, count + 1));
+        Some((min, max, count))
+    }
+}
+
+impl GridCoefficients {
+    /// Calculates the error from compressing and decompressing the coefficients.
+    /// The irradiance error is evaluated for each of the `normals` like [AXIS_NORMALS].
+    pub fn quantization_report(&self, normals: &[[f32; 3]]) -> QuantizationReport {
+        self.report(self.unk5, self.unk6, normals)
+    }
+
+    /// Calculates the error like [GridCoefficients::quantization_report]
+    /// after refitting `unk5` and `unk6` to the coefficients.
+    /// This is the error introduced by re-encoding the grid after converting or editing.
+    pub fn refit_quantization_report(&self, normals: &[[f32; 3]]) -> QuantizationReport {
+        let (unk5, unk6) =
+            sh::compression_params(self.coefficients.iter().flat_map(|c| c.iter().copied()));
+        self.report(unk5, unk6, normals)
+    }
+
+    fn report(&self, unk5: f32, unk6: f32, normals: &[[f32; 3]]) -> QuantizationReport {
+        let mut cells = Vec::new();
+        let mut histograms = Box::new([[[0u32; 256]; 4]; 3]);
+
+        let mut max_coefficient_error = 0.0f32;
+        let mut coefficient_error_sum = 0.0f64;
+        let mut max_irradiance_error = 0.0f32;
+        let mut irradiance_squared_error_sum = 0.0f64;
+        let mut peak = 0.0f32;
+
+        for (index, coefficients) in self.coefficients.iter().enumerate() {
+            let mut cell = CellError {
+                index,
+                max_coefficient_error: 0.0,
+                max_irradiance_error: 0.0,
+                hits_limit: false,
+            };
+
+            for (channel, c) in coefficients.iter().enumerate() {
+                let compressed = sh::compress_coefficients(unk5, unk6, *c);
+                let decompressed = sh::decompress_coefficients(unk5, unk6, compressed);
+
+                // The compressed values are stored in reverse order.
+                for (i, value) in compressed.iter().rev().enumerate() {
+                    histograms[channel][i][*value as usize] += 1;
+                }
+                cell.hits_limit |= compressed.iter().any(|v| *v == 0 || *v == 255);
+
+                for (a, b) in c.iter().zip(decompressed) {
+                    let error = (a - b).abs();
+                    cell.max_coefficient_error = cell.max_coefficient_error.max(error);
+                    coefficient_error_sum += error as f64;
+                }
+
+                for normal in normals {
+                    let expected = sh::irradiance(*c, *normal);
+                    let error = (expected - sh::irradiance(decompressed, *normal)).abs();
+                    cell.max_irradiance_error = cell.max_irradiance_error.max(error);
+                    irradiance_squared_error_sum += (error as f64).powi(2);
+                    peak = peak.max(expected.abs());
+                }
+            }
+
+            max_coefficient_error = max_coefficient_error.max(cell.max_coefficient_error);
+            max_irradiance_error = max_irradiance_error.max(cell.max_irradiance_error);
+            cells.push(cell);
+        }
+
+        let coefficient_count = (self.coefficients.len() * 12).max(1);
+        let irradiance_count = (self.coefficients.len() * 3 * normals.len()).max(1);
+        let mean_squared_error = irradiance_squared_error_sum / irradiance_count as f64;
+
+        QuantizationReport {
+            cells,
+            max_coefficient_error,
+            mean_coefficient_error: (coefficient_error_sum / coefficient_count as f64) as f32,
+            max_irradiance_error,
+            rms_irradiance_error: mean_squared_error.sqrt() as f32,
+            psnr: if mean_squared_error > 0.0 {
+                (10.0 * (peak as f64 * peak as f64 / mean_squared_error).log10()) as f32
+            } else {
+                f32::INFINITY
+            },
+            histograms,
+        }
+    }
+}
+
+impl ShanFile {
+    /// Calculates the [GridCoefficients::quantization_report] for each TPCB.
+    pub fn quantization_reports(&self, normals: &[[f32; 3]]) -> Vec<QuantizationReport> {
+        self.tpcbs
+            .iter()
+            .map(|t| t.coefficients.quantization_report(normals))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{self, shan_file};
+
+    fn grid(coefficients: Vec<[[f32; 4]; 3]>) -> GridCoefficients {
+        let mut grid = test_fixtures::grid([coefficients.len() as u32, 1, 1], coefficients);
+        grid.recalculate_unk5_unk6();
+        grid
+    }
+
+    #[test]
+    fn quantization_report_decompressed_values() {
+        // Values decompressed from bytes should round trip exactly.
+        let unk5 = -1.0247978;
+        let unk6 = 0.0313374;
+        let coefficients = vec![
+            [
+                sh::decompress_coefficients(unk5, unk6, [0, 1, 2, 3]),
+                sh::decompress_coefficients(unk5, unk6, [4, 5, 6, 7]),
+                sh::decompress_coefficients(unk5, unk6, [8, 9, 10, 11]),
+            ],
+            [
+                sh::decompress_coefficients(unk5, unk6, [12, 13, 14, 15]),
+                sh::decompress_coefficients(unk5, unk6, [16, 17, 18, 19]),
+                sh::decompress_coefficients(unk5, unk6, [20, 21, 22, 23]),
+            ],
+        ];
+        let grid = GridCoefficients {
+            unk5,
+            unk6,
+            ..grid(coefficients)
+        };
+
+        let report = grid.quantization_report(&AXIS_NORMALS);
+        assert_eq!(2, report.cells.len());
+        assert_eq!(0.0, report.max_coefficient_error);
+        assert_eq!(0.0, report.mean_coefficient_error);
+        assert_eq!(0.0, report.max_irradiance_error);
+        assert_eq!(0.0, report.rms_irradiance_error);
+        assert_eq!(f32::INFINITY, report.psnr);
+        // The first cell uses the byte 0.
+        assert_eq!(
+            vec![0],
+            report.limit_cells().map(|c| c.index).collect::<Vec<_>>()
+        );
+
+        // Refitting uses a smaller range that doesn't line up with the original steps.
+        let refit = grid.refit_quantization_report(&AXIS_NORMALS);
+        assert!(refit.max_coefficient_error > 0.0);
+        assert!(refit.psnr.is_finite());
+    }
+
+    #[test]
+    fn refit_quantization_report_fitted_grid() {
+        // The grid already uses the fitted range.
+        let grid = grid(vec![[[0.1, 0.2, 0.3, 0.4]; 3], [[1.0, -1.0, 0.5, 2.0]; 3]]);
+        assert_eq!(
+            grid.quantization_report(&AXIS_NORMALS),
+            grid.refit_quantization_report(&AXIS_NORMALS)
+        );
+    }
+
+    #[test]
+    fn quantization_report_error() {
+        let grid = grid(vec![
+            [[0.0, 0.0, 0.0, 0.0]; 3],
+            [[0.1, 0.2, 0.3, 0.4]; 3],
+            [[1.0, 1.0, 1.0, 1.0]; 3],
+        ]);
+
+        let report = grid.quantization_report(&AXIS_NORMALS);
+        // The error is at most half a step for each coefficient.
+        let step = 0.3258 * grid.unk6;
+        assert!(report.max_coefficient_error > 0.0);
+        assert!(report.max_coefficient_error <= step * 0.5 + 0.0001);
+        assert!(report.mean_coefficient_error <= report.max_coefficient_error);
+        assert!(report.rms_irradiance_error <= report.max_irradiance_error);
+        assert!(report.max_irradiance_error <= step * 2.0 + 0.0001);
+        assert!(report.psnr.is_finite() && report.psnr > 30.0);
+
+        // The fitted range always uses the minimum and maximum values.
+        assert!(report.cells[0].hits_limit);
+        assert!(report.cells[2].hits_limit);
+    }
+
+    #[test]
+    fn quantization_report_histograms() {
+        let unk5 = 0.0;
+        let unk6 = 1.0;
+        let coefficients = vec![
+            [sh::decompress_coefficients(unk5, unk6, [1, 2, 3, 4]); 3],
+            [sh::decompress_coefficients(unk5, unk6, [1, 5, 6, 7]); 3],
+        ];
+        let grid = GridCoefficients {
+            unk5,
+            unk6,
+            ..grid(coefficients)
+        };
+
+        let report = grid.quantization_report(&AXIS_NORMALS);
+        // The L0 coefficient is the first compressed byte.
+        assert_eq!(2, report.histograms[0][3][1]);
+        assert_eq!(1, report.histograms[1][0][4]);
+        assert_eq!(1, report.histograms[2][0][7]);
+        assert_eq!(Some((1, 1, 1)), report.byte_usage(0, 3));
+        assert_eq!(Some((4, 7, 2)), report.byte_usage(0, 0));
+        assert_eq!(0, report.limit_cells().count());
+    }
+
+    #[test]
+    fn quantization_report_empty() {
+        let report = grid(Vec::new()).quantization_report(&AXIS_NORMALS);
+        assert!(report.cells.is_empty());
+        assert_eq!(0.0, report.max_coefficient_error);
+        assert_eq!(f32::INFINITY, report.psnr);
+        assert_eq!(None, report.byte_usage(0, 0));
+    }
+
+    #[test]
+    fn quantization_reports_shan_file() {
+        let file = shan_file(vec![
+            (0, grid(vec![[[0.0; 4]; 3]])),
+            (10, grid(vec![[[0.5; 4]; 3]; 2])),
+        ]);
+        let reports = file.quantization_reports(&AXIS_NORMALS);
+        assert_eq!(2, reports.len());
+        assert_eq!(2, reports[1].cells.len());
+    }
+}
@@ -0,0 +1,213 @@
+//! A simple software renderer for previewing probe lighting without a GPU.
+//!
+//! Spheres are drawn with an orthographic camera looking down the negative Z axis with Y up.
+//! Each pixel is shaded by evaluating the L0 and L1 irradiance for the sphere's surface normal.
+use std::path::Path;
+
+use crate::image::HdrImage;
+use crate::{sh, ShanFile};
+
+/// The probes to draw in the rendered image.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RenderTarget {
+    /// A sphere for each probe with the Z slices placed side by side from left to right.
+    /// Each slice has X increasing from left to right and Y increasing from bottom to top.
+    #[default]
+    Grid,
+    /// A single sphere shaded using the coefficients interpolated at a world position.
+    Position([f32; 3]),
+}
+
+/// Settings for rendering probe spheres.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderOptions {
+    pub target: RenderTarget,
+    /// The width and height in pixels of the square tile containing each sphere.
+    pub tile_size: u32,
+    /// The linear color for pixels not covered by a sphere.
+    pub background: [f32; 3],
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            target: RenderTarget::Grid,
+            tile_size: 32,
+            background: [0.0; 3],
+        }
+    }
+}
+
+impl ShanFile {
+    /// Renders the probes at `frame` using the coefficients from [ShanFile::coefficients_at].
+    /// Returns `None` if there are no TPCBs or the coefficients don't match the grid cell count.
+    pub fn render(&self, frame: f32, options: &RenderOptions) -> Option<HdrImage> {
+        let grid = self.coefficients_at(frame)?;
+        let tile = options.tile_size;
+
+        match options.target {
+            RenderTarget::Grid => {
+                let [nx, ny, nz] = grid.grid_cell_count_xyz;
+                let mut image = HdrImage::new(nx * nz * tile, ny * tile, options.background);
+                for z in 0..nz as usize {
+                    for y in 0..ny as usize {
+                        for x in 0..nx as usize {
+                            let cell = grid.cell_index(x, y, z)?;
+                            let coefficients = grid.coefficients.get(cell)?;
+                            let column = z * nx as usize + x;
+                            let row = ny as usize - 1 - y;
+                            draw_sphere(&mut image, column, row, tile, coefficients);
+                        }
+                    }
+                }
+                Some(image)
+            }
+            RenderTarget::Position(position) => {
+                let coefficients = grid.sample(position)?;
+                let mut image = HdrImage::new(tile, tile, options.background);
+                draw_sphere(&mut image, 0, 0, tile, &coefficients);
+                Some(image)
+            }
+        }
+    }
+
+    /// Renders the probes at `frame` and writes the result to `path` as PNG or HDR
+    /// depending on the extension.
+    pub fn render_to_file<P: AsRef<Path>>(
+        &self,
+        frame: f32,
+        path: P,
+        options: &RenderOptions,
+    ) -> std::io::Result<()> {
+        let image = self.render(frame, options).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "No coefficients to render at the given frame",
+            )
+        })?;
+        image.write_to_file(path)
+    }
+}
+
+fn draw_sphere(
+    image: &mut HdrImage,
+    column: usize,
+    row: usize,
+    tile: u32,
+    coefficients: &[[f32; 4]; 3],
+) {
+    let tile = tile as usize;
+    let radius = tile as f32 / 2.0;
+    for py in 0..tile {
+        for px in 0..tile {
+            // Sample at pixel centers with v increasing upwards.
+            let u = (px as f32 + 0.5 - radius) / radius;
+            let v = (radius - py as f32 - 0.5) / radius;
+            let d = u * u + v * v;
+            if d > 1.0 {
+                continue;
+            }
+
+            let normal = [u, v, (1.0 - d).sqrt()];
+            let index = (row * tile + py) * image.width as usize + column * tile + px;
+            image.data[index] = coefficients.map(|c| sh::irradiance(c, normal).max(0.0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{grid, shan_file};
+
+    fn file() -> ShanFile {
+        // The red channel increases with x and the green channel with z.
+        // The blue channel is brighter for normals facing +Y.
+        let coefficients = vec![
+            [[0.0; 4], [0.0; 4], [0.0, 0.5, 0.0, 0.5]],
+            [[0.0, 0.0, 0.0, 1.0], [0.0; 4], [0.0, 0.5, 0.0, 0.5]],
+            [[0.0; 4], [0.0, 0.0, 0.0, 1.0], [0.0, 0.5, 0.0, 0.5]],
+            [
+                [0.0, 0.0, 0.0, 1.0],
+                [0.0, 0.0, 0.0, 1.0],
+                [0.0, 0.5, 0.0, 0.5],
+            ],
+        ];
+        shan_file(vec![(0, grid([2, 1, 2], coefficients))])
+    }
+
+    fn pixel(image: &HdrImage, x: u32, y: u32) -> [f32; 3] {
+        image.data[(y * image.width + x) as usize]
+    }
+
+    #[test]
+    fn render_grid() {
+        let options = RenderOptions {
+            tile_size: 8,
+            background: [0.25; 3],
+            ..Default::default()
+        };
+        let image = file().render(0.0, &options).unwrap();
+        assert_eq!((32, 8), (image.width, image.height));
+
+        // Check the corners and centers of each tile.
+        for (column, [r, g]) in [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [1.0, 1.0]]
+            .into_iter()
+            .enumerate()
+        {
+            let x = column as u32 * 8;
+            assert_eq!([0.25; 3], pixel(&image, x, 0));
+            assert_eq!([0.25; 3], pixel(&image, x + 7, 7));
+
+            let center = pixel(&image, x + 4, 4);
+            assert_eq!([r, g], [center[0], center[1]]);
+        }
+    }
+
+    #[test]
+    fn render_grid_shading() {
+        let options = RenderOptions {
+            tile_size: 16,
+            ..Default::default()
+        };
+        let image = file().render(0.0, &options).unwrap();
+
+        // The top of the sphere faces +Y and the bottom faces -Y.
+        let top = pixel(&image, 8, 1)[2];
+        let center = pixel(&image, 8, 8)[2];
+        let bottom = pixel(&image, 8, 14)[2];
+        assert!(top > center && center > bottom);
+        assert!(top > 0.9 && bottom < 0.1);
+    }
+
+    #[test]
+    fn render_position() {
+        let options = RenderOptions {
+            target: RenderTarget::Position([0.5, 0.0, 1.0]),
+            tile_size: 8,
+            background: [0.0; 3],
+        };
+        let image = file().render(0.0, &options).unwrap();
+        assert_eq!((8, 8), (image.width, image.height));
+
+        let center = pixel(&image, 4, 4);
+        assert_eq!([0.5, 1.0], [center[0], center[1]]);
+        assert_eq!([0.0; 3], pixel(&image, 0, 0));
+    }
+
+    #[test]
+    fn render_no_tpcbs() {
+        let file = ShanFile {
+            name: String::new(),
+            tpcbs: Vec::new(),
+        };
+        assert_eq!(None, file.render(0.0, &RenderOptions::default()));
+    }
+
+    #[test]
+    fn render_missing_coefficients() {
+        let mut file = file();
+        file.tpcbs[0].coefficients.coefficients.truncate(3);
+        assert_eq!(None, file.render(0.0, &RenderOptions::default()));
+    }
+}
@@ -0,0 +1,474 @@
+//! Detecting and repairing values that produce NaN or degenerate coefficients in game.
+use crate::grid::add_scaled;
+use crate::shan::{Shan, TpcbHeader};
+use crate::{GridCoefficients, ShanFile};
+
+/// The reason a TPCB or cell may produce invalid lighting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidReason {
+    /// `unk5` or `unk6` is NaN or infinite, so every decompressed coefficient is also non-finite.
+    NonFiniteUnk5Unk6,
+    /// `unk6` is zero, so every cell decompresses to the same coefficients.
+    ZeroUnk6,
+    /// The spacing for `axis` is zero or non-finite despite having multiple cells.
+    InvalidSpacing { axis: usize },
+    /// The grid bounds contain NaN or infinite values.
+    NonFiniteBounds,
+    /// The number of cells doesn't match the product of the grid cell counts.
+    CellCountMismatch { expected: usize, actual: usize },
+    /// The coefficients for `cell` contain NaN or infinite values.
+    NonFiniteCoefficients { cell: usize },
+}
+
+impl std::fmt::Display for InvalidReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidReason::NonFiniteUnk5Unk6 => write!(f, "unk5 or unk6 is not finite"),
+            InvalidReason::ZeroUnk6 => write!(f, "unk6 is zero"),
+            InvalidReason::InvalidSpacing { axis } => {
+                write!(f, "Spacing for axis {} is zero or not finite", axis)
+            }
+            InvalidReason::NonFiniteBounds => write!(f, "Grid bounds are not finite"),
+            InvalidReason::CellCountMismatch { expected, actual } => write!(
+                f,
+                "Expected {} cells from the grid cell counts but found {}",
+                expected, actual
+            ),
+            InvalidReason::NonFiniteCoefficients { cell } => {
+                write!(f, "Coefficients for cell {} are not finite", cell)
+            }
+        }
+    }
+}
+
+/// An [InvalidReason] for the TPCB at `tpcb_index`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Issue {
+    pub tpcb_index: usize,
+    pub reason: InvalidReason,
+}
+
+impl GridCoefficients {
+    /// Finds values that produce non-finite or degenerate coefficients.
+    pub fn validate(&self) -> Vec<InvalidReason> {
+        let mut reasons = Vec::new();
+        if !self.unk5.is_finite() || !self.unk6.is_finite() {
+            reasons.push(InvalidReason::NonFiniteUnk5Unk6);
+        } else if self.unk6 == 0.0 {
+            reasons.push(InvalidReason::ZeroUnk6);
+        }
+
+        if self
+            .grid_range_min_xyz
+            .iter()
+            .chain(self.grid_range_max_xyz.iter())
+            .any(|v| !v.is_finite())
+        {
+            reasons.push(InvalidReason::NonFiniteBounds);
+        } else {
+            reasons.extend(spacing_reasons(self.grid_cell_count_xyz, self.spacing()));
+        }
+
+        let expected = self.cell_count();
+        if expected != self.coefficients.len() {
+            reasons.push(InvalidReason::CellCountMismatch {
+                expected,
+                actual: self.coefficients.len(),
+            });
+        }
+
+        reasons.extend(
+            self.coefficients
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| !is_finite(c))
+                .map(|(cell, _)| InvalidReason::NonFiniteCoefficients { cell }),
+        );
+        reasons
+    }
+
+    /// Replaces cells with non-finite coefficients with the average of the finite neighboring cells.
+    /// Cells without any finite cells to interpolate from are set to zero.
+    /// `unk5` and `unk6` are recalculated if they aren't finite.
+    /// Returns the number of repaired cells.
+    pub fn repair(&mut self) -> usize {
+        let mut is_invalid: Vec<_> = self.coefficients.iter().map(|c| !is_finite(c)).collect();
+        let mut candidates: Vec<_> = (0..self.coefficients.len())
+            .filter(|i| is_invalid[*i])
+            .collect();
+        let repaired_count = candidates.len();
+
+        // Fill cells from the outside in so cells surrounded by invalid cells
+        // can still use values repaired in previous passes.
+        // Only the neighbors of newly repaired cells can be repaired in the next pass.
+        while !candidates.is_empty() {
+            let repaired: Vec<_> = candidates
+                .iter()
+                .filter_map(|cell| {
+                    let neighbors: Vec<_> =
+                        self.neighbors(*cell).filter(|n| !is_invalid[*n]).collect();
+                    if neighbors.is_empty() {
+                        return None;
+                    }
+
+                    let mut average = [[0.0; 4]; 3];
+                    for n in &neighbors {
+                        add_scaled(
+                            &mut average,
+                            &self.coefficients[*n],
+                            1.0 / neighbors.len() as f32,
+                        );
+                    }
+                    Some((*cell, average))
+                })
+                .collect();
+
+            for (cell, coefficients) in &repaired {
+                self.coefficients[*cell] = *coefficients;
+                is_invalid[*cell] = false;
+            }
+
+            candidates = repaired
+                .iter()
+                .flat_map(|(cell, _)| self.neighbors(*cell))
+                .filter(|n| is_invalid[*n])
+                .collect();
+            candidates.sort_unstable();
+            candidates.dedup();
+        }
+
+        for (coefficients, is_invalid) in self.coefficients.iter_mut().zip(is_invalid) {
+            if is_invalid {
+                *coefficients = [[0.0; 4]; 3];
+            }
+        }
+
+        if !self.unk5.is_finite() || !self.unk6.is_finite() {
+            self.recalculate_unk5_unk6();
+        }
+        repaired_count
+    }
+
+    fn cell_count(&self) -> usize {
+        self.grid_cell_count_xyz
+            .iter()
+            .map(|c| *c as usize)
+            .product()
+    }
+
+    // The cells sharing a face with cell.
+    fn neighbors(&self, cell: usize) -> impl Iterator<Item = usize> + '_ {
+        let [nx, ny, _] = self.grid_cell_count_xyz.map(|c| c as usize);
+        let xyz = if nx > 0 && ny > 0 {
+            [cell % nx, (cell / nx) % ny, cell / (nx * ny)]
+        } else {
+            [0; 3]
+        };
+
+        (0..3)
+            .flat_map(move |axis| {
+                [xyz[axis].checked_sub(1), Some(xyz[axis] + 1)]
+                    .into_iter()
+                    .flatten()
+                    .map(move |i| {
+                        let mut neighbor = xyz;
+                        neighbor[axis] = i;
+                        neighbor
+                    })
+            })
+            .filter_map(|[x, y, z]| self.cell_index(x, y, z))
+            .filter(move |i| *i < self.coefficients.len())
+    }
+}
+
+impl ShanFile {
+    /// Finds values in each TPCB that produce non-finite or degenerate coefficients.
+    pub fn validate(&self) -> Vec<Issue> {
+        self.tpcbs
+            .iter()
+            .enumerate()
+            .flat_map(|(tpcb_index, t)| {
+                t.coefficients
+                    .validate()
+                    .into_iter()
+                    .map(move |reason| Issue { tpcb_index, reason })
+            })
+            .collect()
+    }
+
+    /// Repairs each TPCB using [GridCoefficients::repair].
+    /// Returns the total number of repaired cells.
+    pub fn repair(&mut self) -> usize {
+        self.tpcbs.iter_mut().map(|t| t.coefficients.repair()).sum()
+    }
+}
+
+/// Finds header values that produce non-finite coefficients in game.
+/// This includes header fields like `grid_spacing_xyz` that aren't stored in [GridCoefficients].
+pub fn validate_header(header: &TpcbHeader) -> Vec<InvalidReason> {
+    let mut reasons = Vec::new();
+    if !header.unk5.is_finite() || !header.unk6.is_finite() {
+        reasons.push(InvalidReason::NonFiniteUnk5Unk6);
+    } else if header.unk6 == 0.0 {
+        reasons.push(InvalidReason::ZeroUnk6);
+    }
+
+    if header
+        .grid_range_min_xyz
+        .iter()
+        .chain(header.grid_range_max_xyz.iter())
+        .any(|v| !v.is_finite())
+    {
+        reasons.push(InvalidReason::NonFiniteBounds);
+    }
+    reasons.extend(spacing_reasons(
+        header.grid_cell_count_xyz,
+        header.grid_spacing_xyz,
+    ));
+
+    let expected = header
+        .grid_cell_count_xyz
+        .iter()
+        .map(|c| *c as usize)
+        .product();
+    if header.grid_cell_count as usize != expected {
+        reasons.push(InvalidReason::CellCountMismatch {
+            expected,
+            actual: header.grid_cell_count as usize,
+        });
+    }
+    reasons
+}
+
+/// Finds the header issues from [validate_header] for each TPCB in `shan`.
+/// Null TPCBs are skipped.
+pub fn validate_shan(shan: &Shan) -> Vec<Issue> {
+    shan.tpcbs
+        .iter()
+        .enumerate()
+        .filter_map(|(i, t)| t.as_ref().map(|t| (i, t)))
+        .flat_map(|(tpcb_index, t)| {
+            validate_header(&t.inner.header)
+                .into_iter()
+                .map(move |reason| Issue { tpcb_index, reason })
+        })
+        .collect()
+}
+
+/// Recalculates `grid_dimensions_xyz` and `grid_spacing_xyz` from the grid bounds and cell counts.
+/// The values are only updated if they don't match the recalculated values.
+/// Returns `true` if the header was modified.
+pub fn repair_header(header: &mut TpcbHeader) -> bool {
+    // Use the same calculations as when converting GridCoefficients to Tpcb.
+    let grid = GridCoefficients {
+        grid_cell_count_xyz: header.grid_cell_count_xyz,
+        grid_range_min_xyz: header.grid_range_min_xyz,
+        grid_range_max_xyz: header.grid_range_max_xyz,
+        unk5: header.unk5,
+        unk6: header.unk6,
+        coefficients: Vec::new(),
+    };
+
+    let mut dimensions = [0.0; 3];
+    for (i, d) in dimensions.iter_mut().enumerate() {
+        *d = header.grid_range_max_xyz[i] - header.grid_range_min_xyz[i];
+    }
+    let spacing = grid.spacing();
+
+    let modified = header.grid_dimensions_xyz != dimensions || header.grid_spacing_xyz != spacing;
+    header.grid_dimensions_xyz = dimensions;
+    header.grid_spacing_xyz = spacing;
+    modified
+}
+
+fn spacing_reasons(counts: [u32; 3], spacing: [f32; 3]) -> impl Iterator<Item = InvalidReason> {
+    (0..3)
+        .filter(move |i| counts[*i] > 1 && (spacing[*i] == 0.0 || !spacing[*i].is_finite()))
+        .map(|axis| InvalidReason::InvalidSpacing { axis })
+}
+
+fn is_finite(coefficients: &[[f32; 4]; 3]) -> bool {
+    coefficients.iter().flatten().all(|v| v.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{grid, shan_file};
+
+    fn valid_grid() -> GridCoefficients {
+        GridCoefficients {
+            unk5: -1.0,
+            unk6: 0.5,
+            ..grid([3, 3, 1], (0..9).map(|i| [[i as f32; 4]; 3]).collect())
+        }
+    }
+
+    fn header() -> TpcbHeader {
+        TpcbHeader {
+            unk1_1: 1,
+            unk1_2: 35,
+            grid_cell_count_xyz: [3, 2, 1],
+            grid_spacing_xyz: [1.0, 2.0, 1.0],
+            grid_dimensions_xyz: [2.0, 2.0, 0.0],
+            grid_range_min_xyz: [0.0, 0.0, 0.0],
+            grid_range_max_xyz: [2.0, 2.0, 0.0],
+            unk4: 12,
+            unk5: -1.0,
+            unk6: 0.5,
+            grid_cell_count: 6,
+        }
+    }
+
+    #[test]
+    fn validate_valid() {
+        assert!(valid_grid().validate().is_empty());
+        assert!(validate_header(&header()).is_empty());
+    }
+
+    #[test]
+    fn validate_unk5_unk6() {
+        let mut grid = valid_grid();
+        grid.unk6 = 0.0;
+        assert_eq!(vec![InvalidReason::ZeroUnk6], grid.validate());
+
+        grid.unk5 = f32::NAN;
+        assert_eq!(vec![InvalidReason::NonFiniteUnk5Unk6], grid.validate());
+    }
+
+    #[test]
+    fn validate_zero_spacing() {
+        let mut grid = valid_grid();
+        grid.grid_range_max_xyz[1] = 0.0;
+        assert_eq!(
+            vec![InvalidReason::InvalidSpacing { axis: 1 }],
+            grid.validate()
+        );
+    }
+
+    #[test]
+    fn validate_non_finite_bounds() {
+        let mut grid = valid_grid();
+        grid.grid_range_min_xyz[2] = f32::INFINITY;
+        assert_eq!(vec![InvalidReason::NonFiniteBounds], grid.validate());
+    }
+
+    #[test]
+    fn validate_cells() {
+        let mut grid = valid_grid();
+        grid.coefficients[4][1][2] = f32::NAN;
+        grid.coefficients[7][0][0] = f32::NEG_INFINITY;
+        grid.coefficients.pop();
+        assert_eq!(
+            vec![
+                InvalidReason::CellCountMismatch {
+                    expected: 9,
+                    actual: 8
+                },
+                InvalidReason::NonFiniteCoefficients { cell: 4 },
+                InvalidReason::NonFiniteCoefficients { cell: 7 },
+            ],
+            grid.validate()
+        );
+    }
+
+    #[test]
+    fn validate_shan_file() {
+        let mut invalid = valid_grid();
+        invalid.coefficients[2][0][0] = f32::NAN;
+        let file = shan_file(vec![(0, valid_grid()), (10, invalid)]);
+        assert_eq!(
+            vec![Issue {
+                tpcb_index: 1,
+                reason: InvalidReason::NonFiniteCoefficients { cell: 2 }
+            }],
+            file.validate()
+        );
+    }
+
+    #[test]
+    fn validate_header_spacing_count() {
+        let mut header = header();
+        header.grid_spacing_xyz = [0.0, f32::NAN, 0.0];
+        header.grid_cell_count = 21;
+        assert_eq!(
+            vec![
+                InvalidReason::InvalidSpacing { axis: 0 },
+                InvalidReason::InvalidSpacing { axis: 1 },
+                InvalidReason::CellCountMismatch {
+                    expected: 6,
+                    actual: 21
+                },
+            ],
+            validate_header(&header)
+        );
+    }
+
+    #[test]
+    fn repair_header_spacing() {
+        let mut header = header();
+        assert!(!repair_header(&mut header));
+
+        header.grid_spacing_xyz = [0.0; 3];
+        header.grid_dimensions_xyz = [0.0; 3];
+        assert!(repair_header(&mut header));
+        assert_eq!([1.0, 2.0, 1.0], header.grid_spacing_xyz);
+        assert_eq!([2.0, 2.0, 0.0], header.grid_dimensions_xyz);
+        assert!(validate_header(&header).is_empty());
+    }
+
+    #[test]
+    fn repair_single_cell() {
+        let mut grid = valid_grid();
+        grid.coefficients[4] = [[f32::NAN; 4]; 3];
+        assert_eq!(1, grid.repair());
+        // The average of cells 1, 3, 5, and 7.
+        assert_eq!([[4.0; 4]; 3], grid.coefficients[4]);
+        assert_eq!((-1.0, 0.5), (grid.unk5, grid.unk6));
+        assert!(grid.validate().is_empty());
+    }
+
+    #[test]
+    fn repair_connected_cells() {
+        let mut grid = valid_grid();
+        for cell in [0, 1, 3] {
+            grid.coefficients[cell][2][3] = f32::NAN;
+        }
+        assert_eq!(3, grid.repair());
+        // Cells 1 and 3 are repaired first using their valid neighbors.
+        assert_eq!([[3.0; 4]; 3], grid.coefficients[1]);
+        assert_eq!([[5.0; 4]; 3], grid.coefficients[3]);
+        assert_eq!([[4.0; 4]; 3], grid.coefficients[0]);
+    }
+
+    #[test]
+    fn repair_all_cells() {
+        let mut grid = valid_grid();
+        grid.unk5 = f32::NAN;
+        grid.coefficients = vec![[[f32::NAN; 4]; 3]; 9];
+        assert_eq!(9, grid.repair());
+        assert_eq!(vec![[[0.0; 4]; 3]; 9], grid.coefficients);
+        assert!(grid.unk5.is_finite() && grid.unk6.is_finite());
+    }
+
+    #[test]
+    fn repair_large_grid() {
+        // A stage sized grid with a single valid cell in the corner.
+        let mut coefficients = vec![[[f32::NAN; 4]; 3]; 256 * 256];
+        coefficients[0] = [[1.0; 4]; 3];
+        let mut grid = grid([256, 256, 1], coefficients);
+        assert_eq!(256 * 256 - 1, grid.repair());
+        assert!(grid.coefficients.iter().all(|c| *c == [[1.0; 4]; 3]));
+    }
+
+    #[test]
+    fn repair_shan_file() {
+        let mut invalid = valid_grid();
+        invalid.coefficients[8][0][0] = f32::NAN;
+        let mut file = shan_file(vec![(0, invalid)]);
+        assert_eq!(1, file.repair());
+        // The average of cells 5 and 7.
+        assert_eq!([[6.0; 4]; 3], file.tpcbs[0].coefficients.coefficients[8]);
+        assert!(file.validate().is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+//! Byte-exact verification that reading and writing a file reproduces the original bytes.
+use std::fmt::Display;
+use std::io::Cursor;
+
+use crate::layout::{layout, LayoutRange, RangeKind};
+use crate::shan::Shan;
+
+/// The first difference between the original and rewritten bytes of a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub offset: usize,
+    /// The original byte or `None` if the rewritten file is longer.
+    pub original: Option<u8>,
+    /// The rewritten byte or `None` if the rewritten file is shorter.
+    pub written: Option<u8>,
+    pub original_len: usize,
+    pub written_len: usize,
+    /// The field in the original file containing `offset` from [layout]
+    /// or `None` if the layout couldn't be determined or the offset is past the end of the file.
+    pub field: Option<String>,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let byte = |b: Option<u8>| b.map(|b| format!("{:02X}", b)).unwrap_or("EOF".to_string());
+        write!(
+            f,
+            "First difference at {:#X} ({}): expected {} but found {}. Original size {}, written size {}",
+            self.offset,
+            self.field.as_deref().unwrap_or("unknown field"),
+            byte(self.original),
+            byte(self.written),
+            self.original_len,
+            self.written_len
+        )
+    }
+}
+
+/// Reads `bytes` with [Shan::read] and writes the result in memory with [Shan::write].
+/// Returns the first difference from the original bytes or `None` if the bytes are identical.
+pub fn verify_round_trip(bytes: &[u8]) -> Result<Option<Mismatch>, Box<dyn std::error::Error>> {
+    let shan = Shan::read(&mut Cursor::new(bytes))?;
+
+    let mut writer = Cursor::new(Vec::new());
+    shan.write(&mut writer)?;
+
+    let ranges = layout(bytes).ok();
+    Ok(first_difference(bytes, writer.get_ref(), ranges.as_deref()))
+}
+
+fn first_difference(
+    original: &[u8],
+    written: &[u8],
+    ranges: Option<&[LayoutRange]>,
+) -> Option<Mismatch> {
+    let offset = original
+        .iter()
+        .zip(written)
+        .position(|(a, b)| a != b)
+        .or_else(|| (original.len() != written.len()).then(|| original.len().min(written.len())))?;
+
+    let field = ranges.and_then(|ranges| {
+        ranges
+            .iter()
+            .find(|r| r.start <= offset && offset < r.end)
+            .map(|r| match r.kind {
+                RangeKind::Padding => "padding".to_string(),
+                RangeKind::Unknown => "unknown bytes".to_string(),
+                _ => r.name.clone(),
+            })
+    });
+
+    Some(Mismatch {
+        offset,
+        original: original.get(offset).copied(),
+        written: written.get(offset).copied(),
+        original_len: original.len(),
+        written_len: written.len(),
+        field,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{grid, shan_file};
+    use crate::GridIndexMode;
+
+    fn file_bytes() -> Vec<u8> {
+        let mut file = shan_file(vec![(
+            0,
+            grid([2, 1, 1], vec![[[0.5; 4]; 3], [[-0.5; 4]; 3]]),
+        )]);
+        file.tpcbs[0].coefficients.recalculate_unk5_unk6();
+
+        let mut writer = Cursor::new(Vec::new());
+        file.to_shan(GridIndexMode::Identity)
+            .unwrap()
+            .write(&mut writer)
+            .unwrap();
+        writer.into_inner()
+    }
+
+    fn ranges() -> Vec<LayoutRange> {
+        vec![
+            LayoutRange {
+                start: 0,
+                end: 4,
+                name: "magic".to_string(),
+                kind: RangeKind::Field("\"SHAN\"".to_string()),
+            },
+            LayoutRange {
+                start: 4,
+                end: 8,
+                name: String::new(),
+                kind: RangeKind::Padding,
+            },
+        ]
+    }
+
+    #[test]
+    fn verify_round_trip_written_file() {
+        assert_eq!(None, verify_round_trip(&file_bytes()).unwrap());
+    }
+
+    #[test]
+    fn verify_round_trip_patched_padding() {
+        // The padding after the name is always written as zeros.
+        let mut bytes = file_bytes();
+        bytes[100] = 1;
+
+        let mismatch = verify_round_trip(&bytes).unwrap().unwrap();
+        assert_eq!(100, mismatch.offset);
+        assert_eq!((Some(1), Some(0)), (mismatch.original, mismatch.written));
+        assert_eq!(bytes.len(), mismatch.written_len);
+        assert_eq!(Some("unknown bytes".to_string()), mismatch.field);
+    }
+
+    #[test]
+    fn first_difference_identical() {
+        assert_eq!(None, first_difference(b"SHAN", b"SHAN", None));
+    }
+
+    #[test]
+    fn first_difference_field() {
+        let mismatch = first_difference(b"SHAN\0\0\0\0", b"SHAM\0\0\0\0", Some(&ranges())).unwrap();
+        assert_eq!(3, mismatch.offset);
+        assert_eq!(
+            (Some(b'N'), Some(b'M')),
+            (mismatch.original, mismatch.written)
+        );
+        assert_eq!(Some("magic".to_string()), mismatch.field);
+    }
+
+    #[test]
+    fn first_difference_padding() {
+        let mismatch =
+            first_difference(b"SHAN\0\0\0\0", b"SHAN\0\0\x01\0", Some(&ranges())).unwrap();
+        assert_eq!(6, mismatch.offset);
+        assert_eq!(Some("padding".to_string()), mismatch.field);
+    }
+
+    #[test]
+    fn first_difference_shorter() {
+        let mismatch = first_difference(b"SHAN\0\0\0\0", b"SHAN", Some(&ranges())).unwrap();
+        assert_eq!(4, mismatch.offset);
+        assert_eq!((Some(0), None), (mismatch.original, mismatch.written));
+        assert_eq!((8, 4), (mismatch.original_len, mismatch.written_len));
+        assert_eq!(Some("padding".to_string()), mismatch.field);
+    }
+
+    #[test]
+    fn first_difference_longer() {
+        let mismatch = first_difference(b"SHAN", b"SHAN\0", Some(&ranges()[..1])).unwrap();
+        assert_eq!(4, mismatch.offset);
+        assert_eq!((None, Some(0)), (mismatch.original, mismatch.written));
+        assert_eq!(None, mismatch.field);
+    }
+}
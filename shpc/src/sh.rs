@@ -6,6 +6,7 @@ use glam::{const_vec4, Vec4};
 // vp_c11[19] 0.1481, -0.2962, -0.08551, 0.35544 float4
 // vp_c11[20] 0.1481, -0.2962, -0.08551, 0.35544 float4
 // vp_c11[21] 0.1481, -0.2962, -0.08551, 0.35544 float4
+// Additional captured values are stored in test_data/coeffs.csv.
 // TODO: Should it be possible for decompress -> compress -> decompress to be 1:1 given the low precision (8-bit)?
 const SH_MIN: Vec4 = const_vec4!([0.1481, -0.2962, -0.08551, 0.35544]);
 const SH_SCALE: Vec4 = const_vec4!([0.32573469, 0.32573469, 0.32573469, 0.28209451]);
@@ -47,6 +48,110 @@ pub fn compress_coefficients(unk5: f32, unk6: f32, coefficients: [f32; 4]) -> [u
     [b0 as u8, b1 as u8, b2 as u8, b3 as u8]
 }
 
+/// Evaluates the irradiance for a surface with the given `normal`
+/// using decompressed `coefficients` for a single color channel.
+/// The coefficients already include the basis and cosine lobe convolution constants,
+/// so the result is `L0 + dot(L1, normal)`.
+// TODO: Verify the axis order of the L1 coefficients in game.
+pub fn irradiance(coefficients: [f32; 4], normal: [f32; 3]) -> f32 {
+    let c = Vec4::from(coefficients);
+    c.w + c.truncate().dot(normal.into())
+}
+
+/// The maximum absolute difference in [irradiance] between `a` and `b` for any unit normal.
+pub fn max_irradiance_difference(a: [f32; 4], b: [f32; 4]) -> f32 {
+    let difference = Vec4::from(a) - Vec4::from(b);
+    difference.w.abs() + difference.truncate().length()
+}
+
+/// Calculates the `unk5` and `unk6` values that map the compressed range `0..=255`
+/// onto the full range of `coefficients` to avoid clipping when compressing.
+/// Returns `(0.0, 0.0)` if there are no finite coefficients.
+pub fn compression_params<I: IntoIterator<Item = [f32; 4]>>(coefficients: I) -> (f32, f32) {
+    // Each coefficient decompresses to SH_MIN + SH_SCALE * (unk5 + unk6 * value).
+    let mut min = f32::INFINITY;
+    let mut max = f32::NEG_INFINITY;
+    for c in coefficients {
+        let t = (Vec4::from(c) - SH_MIN) / SH_SCALE;
+        for v in t.to_array().into_iter().filter(|v| v.is_finite()) {
+            min = min.min(v);
+            max = max.max(v);
+        }
+    }
+
+    if min <= max {
+        (min, (max - min) / 255.0)
+    } else {
+        (0.0, 0.0)
+    }
+}
+
+/// A compressed value and the coefficients observed in game after decompression.
+/// This matches the rows of `test_data/coeffs.csv`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressionSample {
+    pub compressed: [u8; 4],
+    pub unk5: f32,
+    pub unk6: f32,
+    pub coefficients: [f32; 4],
+}
+
+impl CompressionSample {
+    pub const CSV_HEADER: &'static str =
+        "compressed[0],compressed[1],compressed[2],compressed[3],unk5,unk6,coeff[0],coeff[1],coeff[2],coeff[3]";
+
+    /// Parses a CSV row with the columns in [CompressionSample::CSV_HEADER].
+    /// Returns `None` for the header or invalid rows.
+    pub fn from_csv_row(row: &str) -> Option<Self> {
+        let values: Vec<_> = row.split(',').map(|v| v.trim()).collect();
+        if values.len() != 10 {
+            return None;
+        }
+
+        let float = |i: usize| values[i].parse::<f32>().ok();
+        Some(Self {
+            compressed: [
+                values[0].parse().ok()?,
+                values[1].parse().ok()?,
+                values[2].parse().ok()?,
+                values[3].parse().ok()?,
+            ],
+            unk5: float(4)?,
+            unk6: float(5)?,
+            coefficients: [float(6)?, float(7)?, float(8)?, float(9)?],
+        })
+    }
+
+    /// Formats the sample as a CSV row with the columns in [CompressionSample::CSV_HEADER].
+    pub fn to_csv_row(&self) -> String {
+        let [c0, c1, c2, c3] = self.compressed;
+        let [f0, f1, f2, f3] = self.coefficients;
+        format!(
+            "{}, {}, {}, {}, {}, {}, {}, {}, {}, {}",
+            c0, c1, c2, c3, self.unk5, self.unk6, f0, f1, f2, f3
+        )
+    }
+}
+
+/// Parses the float4 values from a buffer exported from the RenderDoc buffer viewer.
+/// Lines should have an optional name followed by four comma separated floats like
+/// `vp_c11[19] 0.1481, -0.2962, -0.08551, 0.35544 float4`.
+/// Lines without four floats are skipped.
+pub fn parse_renderdoc_float4s(text: &str) -> Vec<[f32; 4]> {
+    text.lines()
+        .filter_map(|line| {
+            let values: Vec<f32> = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter_map(|v| v.parse().ok())
+                .collect();
+            match values[..] {
+                [a, b, c, d] => Some([a, b, c, d]),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -63,56 +168,86 @@ mod tests {
         };
     }
 
+    fn coeffs_csv_samples() -> Vec<CompressionSample> {
+        let csv = include_str!("../../test_data/coeffs.csv");
+        let mut lines = csv.lines();
+        assert_eq!(Some(CompressionSample::CSV_HEADER), lines.next());
+        lines
+            .map(|line| CompressionSample::from_csv_row(line).unwrap())
+            .collect()
+    }
+
     #[test]
-    fn decompress_sh_coefficients() {
-        // TODO: Put the coefficients first?
-        assert_almost_eq!(
-            [0.1481, -0.2962, -0.08551, 0.35544],
-            decompress_coefficients(0.0, 0.0, [0, 0, 0, 0])
-        );
-        assert_almost_eq!(
-            [0.1481, -0.2962, -0.08551, 0.35544],
-            decompress_coefficients(0.0, 0.0, [128, 128, 128, 128])
-        );
-        assert_almost_eq!(
-            [0.1481, -0.2962, -0.08551, 0.35544],
-            decompress_coefficients(0.0, 0.0, [255, 255, 255, 255])
-        );
-        assert_almost_eq!(
-            [0.1481, -0.29621, -0.08551, 0.35545],
-            decompress_coefficients(0.0, 1.0, [0, 0, 0, 0])
-        );
-        assert_almost_eq!(
-            [41.84214, 41.39783, 41.60854, 36.46354],
-            decompress_coefficients(0.0, 1.0, [128, 128, 128, 128])
-        );
-        assert_almost_eq!(
-            [83.21046, 82.76614, 82.97684, 72.28955],
-            decompress_coefficients(0.0, 1.0, [255, 255, 255, 255])
-        );
-        assert_almost_eq!(
-            [-0.17763, -0.62194, -0.41124, 0.0733],
-            decompress_coefficients(-1.0, 1.0, [0, 0, 0, 0])
-        );
-        assert_almost_eq!(
-            [41.51643, 41.07212, 41.28282, 36.18145],
-            decompress_coefficients(-1.0, 1.0, [128, 128, 128, 128])
-        );
-        assert_almost_eq!(
-            [82.88475, 82.44044, 82.65115, 72.00748],
-            decompress_coefficients(-1.0, 1.0, [255, 255, 255, 255])
+    fn decompress_coeffs_csv() {
+        let samples = coeffs_csv_samples();
+        assert!(!samples.is_empty());
+        for sample in samples {
+            assert_almost_eq!(
+                sample.coefficients,
+                decompress_coefficients(sample.unk5, sample.unk6, sample.compressed)
+            );
+        }
+    }
+
+    #[test]
+    fn compress_coeffs_csv() {
+        for sample in coeffs_csv_samples() {
+            let compressed = compress_coefficients(sample.unk5, sample.unk6, sample.coefficients);
+            if sample.unk6 != 0.0 {
+                assert_eq!(sample.compressed, compressed, "{:?}", sample);
+            } else {
+                // Any compressed values decompress to the same coefficients.
+                assert_almost_eq!(
+                    sample.coefficients,
+                    decompress_coefficients(sample.unk5, sample.unk6, compressed)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn compression_sample_csv_row() {
+        let sample = CompressionSample {
+            compressed: [1, 2, 3, 4],
+            unk5: -1.0,
+            unk6: 0.5,
+            coefficients: [0.1481, -0.2962, -0.08551, 0.35544],
+        };
+        let row = sample.to_csv_row();
+        assert_eq!(
+            "1, 2, 3, 4, -1, 0.5, 0.1481, -0.2962, -0.08551, 0.35544",
+            row
         );
-        assert_almost_eq!(
-            [0.1481, -0.2962, -0.08551, 0.35545],
-            decompress_coefficients(0.0, 2.0, [0, 0, 0, 0])
+        assert_eq!(Some(sample), CompressionSample::from_csv_row(&row));
+    }
+
+    #[test]
+    fn compression_sample_invalid_rows() {
+        assert_eq!(
+            None,
+            CompressionSample::from_csv_row(CompressionSample::CSV_HEADER)
         );
-        assert_almost_eq!(
-            [83.53618, 83.09192, 83.30261, 72.57165],
-            decompress_coefficients(0.0, 2.0, [128, 128, 128, 128])
+        assert_eq!(None, CompressionSample::from_csv_row("0, 0, 0, 0, 0, 0"));
+        assert_eq!(
+            None,
+            CompressionSample::from_csv_row("256, 0, 0, 0, 0, 0, 0, 0, 0, 0")
         );
-        assert_almost_eq!(
-            [166.27286, 165.82857, 166.03926, 144.22372],
-            decompress_coefficients(0.0, 2.0, [255, 255, 255, 255])
+    }
+
+    #[test]
+    fn parse_renderdoc_buffer() {
+        let text = "Name Value Type\n\
+            vp_c11[19] 0.1481, -0.2962, -0.08551, 0.35544 float4\n\
+            vp_c11[20] 1, 2.5, -3, 4e-2 float4\n\
+            vp_c11[21] 0.5, 0.25 float2\n\
+            0.0, 0.0, 0.0, 1.0\n";
+        assert_eq!(
+            vec![
+                [0.1481, -0.2962, -0.08551, 0.35544],
+                [1.0, 2.5, -3.0, 0.04],
+                [0.0, 0.0, 0.0, 1.0]
+            ],
+            parse_renderdoc_float4s(text)
         );
     }
 
@@ -210,4 +345,43 @@ mod tests {
             compress_coefficients(0.0, 0.0, [0.1481, -0.2962, -0.08551, 0.35544])
         );
     }
+
+    #[test]
+    fn compression_params_round_trip() {
+        let coefficients = [
+            [0.5, -0.25, 1.0, 2.0],
+            [-1.0, 0.0, 0.25, 0.5],
+            [0.0, 0.75, -0.5, 1.25],
+        ];
+        let (unk5, unk6) = compression_params(coefficients);
+        for c in coefficients {
+            let compressed = compress_coefficients(unk5, unk6, c);
+            let decompressed = decompress_coefficients(unk5, unk6, compressed);
+            // The error should be at most half a step.
+            for (a, b) in c.iter().zip(decompressed.iter()) {
+                assert!((a - b).abs() <= 0.33 * unk6 * 0.5 + 0.0001);
+            }
+        }
+    }
+
+    #[test]
+    fn compression_params_empty() {
+        assert_eq!((0.0, 0.0), compression_params([]));
+    }
+
+    #[test]
+    fn irradiance_l0_l1() {
+        let coefficients = [0.25, -0.5, 0.0, 1.0];
+        assert_eq!(1.0, irradiance(coefficients, [0.0, 0.0, 1.0]));
+        assert_eq!(1.25, irradiance(coefficients, [1.0, 0.0, 0.0]));
+        assert_eq!(1.5, irradiance(coefficients, [0.0, -1.0, 0.0]));
+    }
+
+    #[test]
+    fn max_irradiance_difference_l0_l1() {
+        assert_eq!(
+            1.5,
+            max_irradiance_difference([0.3, 0.4, 0.0, 1.0], [0.0, 0.0, 0.0, 2.0])
+        );
+    }
 }
@@ -41,9 +41,12 @@ impl Shan {
     }
 
     /// Tries to read the data from `reader`.
+    /// The entire input is buffered, so non seekable readers like [std::io::Stdin] are supported.
     /// For best performance when opening from a file, use [Shan::from_file] instead.
-    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Self, Box<dyn std::error::Error>> {
-        reader.read_le().map_err(Into::into)
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Cursor::new(bytes).read_le().map_err(Into::into)
     }
 
     /// Writes to the given `writer`.
@@ -95,12 +98,16 @@ pub struct TpcbInner {
 
     // TODO: This needs to account for alignment.
     // Subtract the magic size from each offset.
-    /// The range `0..grid_cell_count` not including `grid_cell_count`.
+    // Grids default to null when deserializing since formats like TOML omit None values.
+    /// The index into `grid_sh_coefficients` for each cell.
+    /// This is usually the range `0..grid_cell_count` not including `grid_cell_count`.
     #[br(args(header.grid_cell_count, base_offset - 4, offset1))]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub grid_indices: Grid<u16>,
 
     /// Compressed spherical harmonic coefficients in row-major order for x -> y -> z.
     #[br(args(header.grid_cell_count, base_offset - 4, offset2))]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub grid_sh_coefficients: Grid<CompressedShCoefficients>,
 
     // TODO: This value isn't always present.
@@ -109,6 +116,7 @@ pub struct TpcbInner {
     // TODO: Test using a small probe count and different colors.
     // Only used for stage and not chara lighting?
     #[br(args(header.grid_cell_count, base_offset - 4, offset3))]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub grid_unk_values: Grid<[f32; 3]>,
 }
 
@@ -148,6 +156,12 @@ pub struct TpcbHeader {
 #[derive(Debug, SsbhWrite, Clone, PartialEq)]
 pub struct Grid<T: BinRead<Args = ()> + SsbhWrite>(pub Option<Vec<T>>);
 
+impl<T: BinRead<Args = ()> + SsbhWrite> Default for Grid<T> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
 impl<T: BinRead<Args = ()> + SsbhWrite> BinRead for Grid<T> {
     type Args = (u32, u64, u32);
 
@@ -266,3 +280,28 @@ impl Debug for NameStr {
         write!(f, "\"{}\"", self.to_string_lossy())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{grid, shan_file};
+    use crate::GridIndexMode;
+
+    #[test]
+    fn read_non_seekable_reader() {
+        let mut file = shan_file(vec![(
+            0,
+            grid([2, 1, 1], vec![[[0.5; 4]; 3], [[-0.5; 4]; 3]]),
+        )]);
+        file.tpcbs[0].coefficients.recalculate_unk5_unk6();
+        let shan = file.to_shan(GridIndexMode::Identity).unwrap();
+
+        let mut writer = Cursor::new(Vec::new());
+        shan.write(&mut writer).unwrap();
+        let bytes = writer.into_inner();
+
+        // Slices implement Read but not Seek.
+        let result = Shan::read(&mut &bytes[..]).unwrap();
+        assert_eq!(shan, result);
+    }
+}
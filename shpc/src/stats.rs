@@ -0,0 +1,235 @@
+//! Summary statistics for the lighting and grid layout of each TPCB.
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::color::{luminance, LUMINANCE_WEIGHTS};
+use crate::{GridCoefficients, ShanFile};
+
+/// The minimum, maximum, and mean of a set of values.
+/// All values are `0.0` if there are no values.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ValueStats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+}
+
+/// Statistics for the lighting of a set of cells.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LightingStats {
+    pub cell_count: usize,
+    /// The [luminance] of the L0 colors.
+    pub l0_luminance: ValueStats,
+    /// The mean length of the luminance weighted L1 vectors.
+    pub l1_magnitude: f32,
+    /// The normalized sum of the luminance weighted L1 vectors
+    /// or zero if the vectors cancel out.
+    pub l1_direction: [f32; 3],
+    /// The range of each float coefficient indexed by `[channel][coefficient]`.
+    pub coefficient_min: [[f32; 4]; 3],
+    pub coefficient_max: [[f32; 4]; 3],
+}
+
+/// Statistics for a single TPCB.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TpcbStats {
+    pub starting_frame: u32,
+    pub grid_cell_count_xyz: [u32; 3],
+    pub grid_range_min_xyz: [f32; 3],
+    pub grid_range_max_xyz: [f32; 3],
+    pub grid_spacing_xyz: [f32; 3],
+    pub unk5: f32,
+    pub unk6: f32,
+    pub lighting: LightingStats,
+}
+
+/// Statistics for all TPCBs in a file.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShanStats {
+    pub name: String,
+    /// The lighting statistics for the cells of all TPCBs.
+    pub lighting: LightingStats,
+    pub tpcbs: Vec<TpcbStats>,
+}
+
+impl ShanFile {
+    /// Calculates statistics for the entire file and each TPCB.
+    pub fn stats(&self) -> ShanStats {
+        ShanStats {
+            name: self.name.clone(),
+            lighting: lighting_stats(self.tpcbs.iter().flat_map(|t| &t.coefficients.coefficients)),
+            tpcbs: self
+                .tpcbs
+                .iter()
+                .map(|t| t.coefficients.stats(t.starting_frame))
+                .collect(),
+        }
+    }
+}
+
+impl GridCoefficients {
+    fn stats(&self, starting_frame: u32) -> TpcbStats {
+        TpcbStats {
+            starting_frame,
+            grid_cell_count_xyz: self.grid_cell_count_xyz,
+            grid_range_min_xyz: self.grid_range_min_xyz,
+            grid_range_max_xyz: self.grid_range_max_xyz,
+            grid_spacing_xyz: self.spacing(),
+            unk5: self.unk5,
+            unk6: self.unk6,
+            lighting: lighting_stats(&self.coefficients),
+        }
+    }
+}
+
+fn lighting_stats<'a, I: IntoIterator<Item = &'a [[f32; 4]; 3]>>(cells: I) -> LightingStats {
+    let mut cell_count = 0;
+    let mut l0_luminance = ValueStats {
+        min: f32::INFINITY,
+        max: f32::NEG_INFINITY,
+        mean: 0.0,
+    };
+    let mut l1_magnitude = 0.0;
+    let mut l1_sum = [0.0f32; 3];
+    let mut coefficient_min = [[f32::INFINITY; 4]; 3];
+    let mut coefficient_max = [[f32::NEG_INFINITY; 4]; 3];
+
+    for cell in cells {
+        cell_count += 1;
+
+        let l = luminance(cell.map(|c| c[3]));
+        l0_luminance.min = l0_luminance.min.min(l);
+        l0_luminance.max = l0_luminance.max.max(l);
+        l0_luminance.mean += l;
+
+        let mut l1 = [0.0f32; 3];
+        for (c, weight) in cell.iter().zip(LUMINANCE_WEIGHTS) {
+            for (l1, value) in l1.iter_mut().zip(c) {
+                *l1 += value * weight;
+            }
+        }
+        l1_magnitude += l1.iter().map(|v| v * v).sum::<f32>().sqrt();
+        for (sum, v) in l1_sum.iter_mut().zip(l1) {
+            *sum += v;
+        }
+
+        for (channel, c) in cell.iter().enumerate() {
+            for (i, value) in c.iter().enumerate() {
+                coefficient_min[channel][i] = coefficient_min[channel][i].min(*value);
+                coefficient_max[channel][i] = coefficient_max[channel][i].max(*value);
+            }
+        }
+    }
+
+    if cell_count == 0 {
+        return LightingStats::default();
+    }
+
+    l0_luminance.mean /= cell_count as f32;
+    let length = l1_sum.iter().map(|v| v * v).sum::<f32>().sqrt();
+    let l1_direction = if length > 0.0 {
+        l1_sum.map(|v| v / length)
+    } else {
+        [0.0; 3]
+    };
+
+    LightingStats {
+        cell_count,
+        l0_luminance,
+        l1_magnitude: l1_magnitude / cell_count as f32,
+        l1_direction,
+        coefficient_min,
+        coefficient_max,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{grid, shan_file};
+
+    fn file() -> ShanFile {
+        let coefficients = vec![
+            [[0.0, 1.0, 0.0, 1.0]; 3],
+            [
+                [0.0, 0.0, 0.0, 3.0],
+                [0.0, 0.0, 0.0, 3.0],
+                [-1.0, 0.0, 0.5, 3.0],
+            ],
+        ];
+        shan_file(vec![
+            (
+                0,
+                GridCoefficients {
+                    grid_range_min_xyz: [-1.0, 0.0, 0.0],
+                    grid_range_max_xyz: [1.0, 0.0, 0.0],
+                    unk5: -1.0,
+                    unk6: 0.5,
+                    ..grid([2, 1, 1], coefficients)
+                },
+            ),
+            (10, grid([1, 1, 1], vec![[[0.0, -3.0, 0.0, 2.0]; 3]])),
+        ])
+    }
+
+    #[test]
+    fn tpcb_stats() {
+        let stats = file().stats();
+        assert_eq!("chara", stats.name);
+        assert_eq!(2, stats.tpcbs.len());
+
+        let tpcb = &stats.tpcbs[0];
+        assert_eq!(0, tpcb.starting_frame);
+        assert_eq!([2.0, 1.0, 1.0], tpcb.grid_spacing_xyz);
+        assert_eq!((-1.0, 0.5), (tpcb.unk5, tpcb.unk6));
+
+        let lighting = &tpcb.lighting;
+        assert_eq!(2, lighting.cell_count);
+        assert!((lighting.l0_luminance.min - 1.0).abs() < 0.0001);
+        assert!((lighting.l0_luminance.max - 3.0).abs() < 0.0001);
+        assert!((lighting.l0_luminance.mean - 2.0).abs() < 0.0001);
+        assert_eq!([-1.0, 0.0, 0.0, 1.0], lighting.coefficient_min[2]);
+        assert_eq!([0.0, 1.0, 0.5, 3.0], lighting.coefficient_max[2]);
+        assert_eq!([0.0, 1.0, 0.0, 3.0], lighting.coefficient_max[0]);
+    }
+
+    #[test]
+    fn l1_magnitude_direction() {
+        let stats = file().stats();
+
+        // The first cell has a magnitude of 1.0 along +Y.
+        // The second cell only has blue L1.
+        let lighting = &stats.tpcbs[0].lighting;
+        let blue = 0.0722 * (1.0f32 + 0.25).sqrt();
+        assert!((lighting.l1_magnitude - (1.0 + blue) / 2.0).abs() < 0.0001);
+        assert!(lighting.l1_direction[1] > 0.9);
+        assert!(lighting.l1_direction[0] < 0.0);
+
+        let lighting = &stats.tpcbs[1].lighting;
+        assert!((lighting.l1_magnitude - 3.0).abs() < 0.0001);
+        assert_eq!([0.0, -1.0, 0.0], lighting.l1_direction);
+    }
+
+    #[test]
+    fn file_stats() {
+        let stats = file().stats();
+        assert_eq!(3, stats.lighting.cell_count);
+        assert!((stats.lighting.l0_luminance.mean - 2.0).abs() < 0.0001);
+        assert_eq!([-1.0, -3.0, 0.0, 1.0], stats.lighting.coefficient_min[2]);
+    }
+
+    #[test]
+    fn empty_stats() {
+        let file = ShanFile {
+            name: String::new(),
+            tpcbs: Vec::new(),
+        };
+        let stats = file.stats();
+        assert!(stats.tpcbs.is_empty());
+        assert_eq!(LightingStats::default(), stats.lighting);
+    }
+}
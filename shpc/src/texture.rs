@@ -0,0 +1,538 @@
+//! Conversions between coefficient grids and 3D volume textures in DDS and KTX2 containers.
+//!
+//! Textures use an uncompressed RGBA 32-bit float format with texels in the same
+//! row-major order for x -> y -> z as the grid coefficients.
+//! The textures don't store the world space bounds of the grid,
+//! so these need to be supplied separately when importing.
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::Path;
+
+use crate::GridCoefficients;
+
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+const DXGI_FORMAT_R32G32B32A32_FLOAT: u32 = 2;
+const D3D10_RESOURCE_DIMENSION_TEXTURE3D: u32 = 4;
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const VK_FORMAT_R32G32B32A32_SFLOAT: u32 = 109;
+
+const TEXEL_SIZE: usize = 16;
+
+/// The assignment of coefficients to textures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VolumeLayout {
+    /// A texture for each of the 4 coefficients with the RGB values in the RGB channels.
+    /// The alpha channel is always `1.0`.
+    PerCoefficient,
+    /// A texture for each of the red, green, and blue channels with the 4 coefficients in RGBA.
+    /// This matches the layout of the coefficients in the game's uniform buffer.
+    Packed,
+}
+
+/// An uncompressed 3D texture with 4 float channels per texel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VolumeTexture {
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    /// The `width * height * depth` texels in row-major order for x -> y -> z.
+    pub data: Vec<[f32; 4]>,
+}
+
+impl VolumeTexture {
+    /// Writes the texture to `path` as DDS or KTX2 depending on the extension.
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let mut buffer = Vec::new();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("dds") => self.write_dds(&mut buffer)?,
+            Some("ktx2") => self.write_ktx2(&mut buffer)?,
+            _ => return Err(invalid_input("Expected a .dds or .ktx2 file extension")),
+        }
+        std::fs::write(path, buffer)
+    }
+
+    /// Reads a DDS or KTX2 texture from `path` depending on the extension.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("dds") => Self::read_dds(&mut bytes.as_slice()),
+            Some("ktx2") => Self::read_ktx2(&mut bytes.as_slice()),
+            _ => Err(invalid_input("Expected a .dds or .ktx2 file extension")),
+        }
+    }
+
+    /// Writes the texture as DDS with a DX10 header and DXGI_FORMAT_R32G32B32A32_FLOAT.
+    pub fn write_dds<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        // DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PITCH | DDSD_PIXELFORMAT | DDSD_DEPTH
+        let flags: u32 = 0x1 | 0x2 | 0x4 | 0x8 | 0x1000 | 0x800000;
+        // DDSCAPS_TEXTURE
+        let caps: u32 = 0x1000;
+        // DDSCAPS2_VOLUME
+        let caps2: u32 = 0x200000;
+
+        let mut header = Vec::new();
+        header.extend_from_slice(DDS_MAGIC);
+        for value in [
+            124,
+            flags,
+            self.height,
+            self.width,
+            self.width * TEXEL_SIZE as u32,
+            self.depth,
+            1,
+        ] {
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+        header.extend_from_slice(&[0u8; 44]);
+
+        // DDS_PIXELFORMAT using DDPF_FOURCC to indicate the DX10 header.
+        header.extend_from_slice(&32u32.to_le_bytes());
+        header.extend_from_slice(&0x4u32.to_le_bytes());
+        header.extend_from_slice(b"DX10");
+        header.extend_from_slice(&[0u8; 20]);
+
+        for value in [caps, caps2, 0, 0, 0] {
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+
+        // DDS_HEADER_DXT10
+        for value in [
+            DXGI_FORMAT_R32G32B32A32_FLOAT,
+            D3D10_RESOURCE_DIMENSION_TEXTURE3D,
+            0,
+            1,
+            0,
+        ] {
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+
+        writer.write_all(&header)?;
+        writer.write_all(&self.texel_bytes())
+    }
+
+    /// Reads a DDS texture with a DX10 header and DXGI_FORMAT_R32G32B32A32_FLOAT.
+    pub fn read_dds<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut header = [0u8; 148];
+        reader.read_exact(&mut header)?;
+        if &header[0..4] != DDS_MAGIC {
+            return Err(invalid_data("Invalid DDS magic"));
+        }
+        if &header[84..88] != b"DX10" {
+            return Err(invalid_data("Expected a DDS file with a DX10 header"));
+        }
+
+        let format = read_u32(&header, 128);
+        if format != DXGI_FORMAT_R32G32B32A32_FLOAT {
+            return Err(invalid_data(&format!(
+                "Unsupported DXGI format {}. Expected R32G32B32A32_FLOAT",
+                format
+            )));
+        }
+
+        let height = read_u32(&header, 12);
+        let width = read_u32(&header, 16);
+        let depth = read_u32(&header, 24).max(1);
+        Self::read_texels(reader, width, height, depth)
+    }
+
+    /// Writes the texture as KTX2 with VK_FORMAT_R32G32B32A32_SFLOAT.
+    pub fn write_ktx2<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let dfd = ktx2_dfd();
+
+        // The header, index, and a single level index entry.
+        let dfd_offset = 80 + 24;
+        // The level data is aligned to the texel size.
+        let level_offset = (dfd_offset + dfd.len()).div_ceil(TEXEL_SIZE) * TEXEL_SIZE;
+        let texels = self.texel_bytes();
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&KTX2_IDENTIFIER);
+        for value in [
+            VK_FORMAT_R32G32B32A32_SFLOAT,
+            4,
+            self.width,
+            self.height,
+            self.depth,
+            0,
+            1,
+            1,
+            0,
+        ] {
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+
+        // DFD and KVD offsets and lengths.
+        for value in [dfd_offset as u32, dfd.len() as u32, 0, 0] {
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+        // SGD offset and length.
+        header.extend_from_slice(&[0u8; 16]);
+
+        for value in [level_offset, texels.len(), texels.len()] {
+            header.extend_from_slice(&(value as u64).to_le_bytes());
+        }
+
+        header.extend_from_slice(&dfd);
+        header.resize(level_offset, 0u8);
+
+        writer.write_all(&header)?;
+        writer.write_all(&texels)
+    }
+
+    /// Reads a KTX2 texture with VK_FORMAT_R32G32B32A32_SFLOAT and no supercompression.
+    pub fn read_ktx2<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        if bytes.len() < 104 || bytes[0..12] != KTX2_IDENTIFIER {
+            return Err(invalid_data("Invalid KTX2 identifier"));
+        }
+
+        let format = read_u32(&bytes, 12);
+        if format != VK_FORMAT_R32G32B32A32_SFLOAT {
+            return Err(invalid_data(&format!(
+                "Unsupported Vulkan format {}. Expected R32G32B32A32_SFLOAT",
+                format
+            )));
+        }
+        if read_u32(&bytes, 44) != 0 {
+            return Err(invalid_data("Supercompressed KTX2 files are not supported"));
+        }
+
+        let width = read_u32(&bytes, 20);
+        let height = read_u32(&bytes, 24).max(1);
+        let depth = read_u32(&bytes, 28).max(1);
+
+        // Only read the base level.
+        let level_offset = read_u64(&bytes, 80) as usize;
+        let level_length = read_u64(&bytes, 88) as usize;
+        let level = level_offset
+            .checked_add(level_length)
+            .and_then(|level_end| bytes.get(level_offset..level_end))
+            .ok_or_else(|| invalid_data("Level data is out of range"))?;
+        if texel_byte_count(width, height, depth)? > level.len() {
+            return Err(invalid_data(
+                "Level data is too small for the texture dimensions",
+            ));
+        }
+        Self::read_texels(&mut &level[..], width, height, depth)
+    }
+
+    fn texel_bytes(&self) -> Vec<u8> {
+        self.data
+            .iter()
+            .flat_map(|texel| texel.iter().flat_map(|v| v.to_le_bytes()))
+            .collect()
+    }
+
+    fn read_texels<R: Read>(
+        reader: &mut R,
+        width: u32,
+        height: u32,
+        depth: u32,
+    ) -> std::io::Result<Self> {
+        // Don't allocate based on the header dimensions in case the data is truncated.
+        let byte_count = texel_byte_count(width, height, depth)?;
+        let mut bytes = Vec::new();
+        reader.take(byte_count as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != byte_count {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "Texel data is too small for the texture dimensions",
+            ));
+        }
+
+        let data = bytes
+            .chunks_exact(TEXEL_SIZE)
+            .map(|texel| [0, 1, 2, 3].map(|i| f32::from_le_bytes(read_array(texel, i * 4))))
+            .collect();
+
+        Ok(Self {
+            width,
+            height,
+            depth,
+            data,
+        })
+    }
+}
+
+impl GridCoefficients {
+    /// Converts the coefficients to 3D textures with the grid cell counts as dimensions.
+    pub fn to_volume_textures(&self, layout: VolumeLayout) -> Vec<VolumeTexture> {
+        let [width, height, depth] = self.grid_cell_count_xyz;
+        let texture = |data| VolumeTexture {
+            width,
+            height,
+            depth,
+            data,
+        };
+
+        match layout {
+            VolumeLayout::PerCoefficient => (0..4)
+                .map(|i| {
+                    texture(
+                        self.coefficients
+                            .iter()
+                            .map(|c| [c[0][i], c[1][i], c[2][i], 1.0])
+                            .collect(),
+                    )
+                })
+                .collect(),
+            VolumeLayout::Packed => (0..3)
+                .map(|channel| texture(self.coefficients.iter().map(|c| c[channel]).collect()))
+                .collect(),
+        }
+    }
+
+    /// Creates a grid from textures created with [GridCoefficients::to_volume_textures].
+    /// The `unk5` and `unk6` values are calculated to fit the coefficients.
+    pub fn from_volume_textures(
+        textures: &[VolumeTexture],
+        layout: VolumeLayout,
+        grid_range_min_xyz: [f32; 3],
+        grid_range_max_xyz: [f32; 3],
+    ) -> std::io::Result<Self> {
+        let expected_count = match layout {
+            VolumeLayout::PerCoefficient => 4,
+            VolumeLayout::Packed => 3,
+        };
+        if textures.len() != expected_count {
+            return Err(invalid_input(&format!(
+                "Expected {} textures but found {}",
+                expected_count,
+                textures.len()
+            )));
+        }
+
+        let first = &textures[0];
+        let cell_count = first.width as usize * first.height as usize * first.depth as usize;
+        if textures.iter().any(|t| {
+            [t.width, t.height, t.depth] != [first.width, first.height, first.depth]
+                || t.data.len() != cell_count
+        }) {
+            return Err(invalid_input("All textures must have the same dimensions"));
+        }
+
+        let coefficients = (0..cell_count)
+            .map(|cell| match layout {
+                VolumeLayout::PerCoefficient => {
+                    let mut c = [[0.0; 4]; 3];
+                    for (i, texture) in textures.iter().enumerate() {
+                        for (channel, value) in c.iter_mut().enumerate() {
+                            value[i] = texture.data[cell][channel];
+                        }
+                    }
+                    c
+                }
+                VolumeLayout::Packed => [
+                    textures[0].data[cell],
+                    textures[1].data[cell],
+                    textures[2].data[cell],
+                ],
+            })
+            .collect();
+
+        let mut grid = Self {
+            grid_cell_count_xyz: [first.width, first.height, first.depth],
+            grid_range_min_xyz,
+            grid_range_max_xyz,
+            unk5: 0.0,
+            unk6: 0.0,
+            coefficients,
+        };
+        grid.recalculate_unk5_unk6();
+        Ok(grid)
+    }
+}
+
+// The data format descriptor with a single basic block for RGBA 32-bit float.
+fn ktx2_dfd() -> Vec<u8> {
+    let block_size = 24 + 16 * 4;
+
+    let mut dfd = Vec::new();
+    dfd.extend_from_slice(&(4 + block_size as u32).to_le_bytes());
+    // vendorId and descriptorType
+    dfd.extend_from_slice(&0u32.to_le_bytes());
+    // versionNumber and descriptorBlockSize
+    dfd.extend_from_slice(&2u16.to_le_bytes());
+    dfd.extend_from_slice(&(block_size as u16).to_le_bytes());
+    // KHR_DF_MODEL_RGBSDA, KHR_DF_PRIMARIES_BT709, KHR_DF_TRANSFER_LINEAR, and no flags
+    dfd.extend_from_slice(&[1, 1, 1, 0]);
+    // texelBlockDimension
+    dfd.extend_from_slice(&[0, 0, 0, 0]);
+    // bytesPlane0..7
+    dfd.extend_from_slice(&[TEXEL_SIZE as u8, 0, 0, 0, 0, 0, 0, 0]);
+
+    // Samples for the R, G, B, and A channels with the float and signed qualifiers.
+    for (i, channel_id) in [0u8, 1, 2, 15].iter().enumerate() {
+        dfd.extend_from_slice(&(i as u16 * 32).to_le_bytes());
+        dfd.push(31);
+        dfd.push(channel_id | 0xC0);
+        dfd.extend_from_slice(&[0, 0, 0, 0]);
+        dfd.extend_from_slice(&(-1.0f32).to_bits().to_le_bytes());
+        dfd.extend_from_slice(&1.0f32.to_bits().to_le_bytes());
+    }
+    dfd
+}
+
+fn read_array<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    let mut array = [0u8; N];
+    array.copy_from_slice(&bytes[offset..offset + N]);
+    array
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(read_array(bytes, offset))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(read_array(bytes, offset))
+}
+
+fn texel_byte_count(width: u32, height: u32, depth: u32) -> std::io::Result<usize> {
+    (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|n| n.checked_mul(depth as usize))
+        .and_then(|n| n.checked_mul(TEXEL_SIZE))
+        .ok_or_else(|| invalid_data("Texture dimensions are too large"))
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn invalid_input(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures;
+
+    fn grid() -> GridCoefficients {
+        let coefficients = (0..6)
+            .map(|i| {
+                let v = i as f32;
+                [
+                    [v, v + 0.1, v + 0.2, v + 0.3],
+                    [-v, 0.5, 0.25, 1.0],
+                    [0.0, 0.0, v * 2.0, 2.0],
+                ]
+            })
+            .collect();
+        test_fixtures::grid([3, 2, 1], coefficients)
+    }
+
+    #[test]
+    fn dds_round_trip() {
+        let texture = &grid().to_volume_textures(VolumeLayout::Packed)[0];
+
+        let mut dds = Vec::new();
+        texture.write_dds(&mut dds).unwrap();
+        assert_eq!(148 + 6 * 16, dds.len());
+        assert_eq!(b"DDS ", &dds[0..4]);
+
+        let new_texture = VolumeTexture::read_dds(&mut dds.as_slice()).unwrap();
+        assert_eq!(texture, &new_texture);
+    }
+
+    #[test]
+    fn ktx2_round_trip() {
+        let texture = &grid().to_volume_textures(VolumeLayout::PerCoefficient)[3];
+
+        let mut ktx2 = Vec::new();
+        texture.write_ktx2(&mut ktx2).unwrap();
+        assert_eq!(KTX2_IDENTIFIER, ktx2[0..12]);
+        // The level data should be aligned to the texel size.
+        assert_eq!(0, read_u64(&ktx2, 80) % 16);
+
+        let new_texture = VolumeTexture::read_ktx2(&mut ktx2.as_slice()).unwrap();
+        assert_eq!(texture, &new_texture);
+    }
+
+    #[test]
+    fn read_ktx2_invalid_level_range() {
+        let texture = &grid().to_volume_textures(VolumeLayout::Packed)[0];
+        let mut ktx2 = Vec::new();
+        texture.write_ktx2(&mut ktx2).unwrap();
+
+        // The level offset and length shouldn't overflow.
+        let mut bytes = ktx2.clone();
+        bytes[88..96].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(VolumeTexture::read_ktx2(&mut bytes.as_slice()).is_err());
+
+        // The dimensions shouldn't allocate more than the level data.
+        let mut bytes = ktx2.clone();
+        for offset in [20, 24, 28] {
+            bytes[offset..offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        }
+        assert!(VolumeTexture::read_ktx2(&mut bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn read_dds_truncated() {
+        let texture = &grid().to_volume_textures(VolumeLayout::Packed)[0];
+        let mut dds = Vec::new();
+        texture.write_dds(&mut dds).unwrap();
+
+        let result = VolumeTexture::read_dds(&mut &dds[..dds.len() - 1]);
+        assert_eq!(ErrorKind::UnexpectedEof, result.unwrap_err().kind());
+    }
+
+    #[test]
+    fn read_dds_invalid_magic() {
+        let bytes = [0u8; 148];
+        assert!(VolumeTexture::read_dds(&mut &bytes[..]).is_err());
+    }
+
+    #[test]
+    fn per_coefficient_layout() {
+        let textures = grid().to_volume_textures(VolumeLayout::PerCoefficient);
+        assert_eq!(4, textures.len());
+        assert_eq!(
+            [3, 2, 1],
+            [textures[0].width, textures[0].height, textures[0].depth]
+        );
+        assert_eq!([5.3, 1.0, 2.0, 1.0], textures[3].data[5]);
+    }
+
+    #[test]
+    fn packed_layout() {
+        let textures = grid().to_volume_textures(VolumeLayout::Packed);
+        assert_eq!(3, textures.len());
+        assert_eq!([0.0, 0.0, 4.0, 2.0], textures[2].data[2]);
+    }
+
+    #[test]
+    fn grid_round_trip() {
+        let grid = grid();
+        for layout in [VolumeLayout::PerCoefficient, VolumeLayout::Packed] {
+            let textures = grid.to_volume_textures(layout);
+            let new_grid = GridCoefficients::from_volume_textures(
+                &textures,
+                layout,
+                grid.grid_range_min_xyz,
+                grid.grid_range_max_xyz,
+            )
+            .unwrap();
+            assert_eq!(grid.grid_cell_count_xyz, new_grid.grid_cell_count_xyz);
+            assert_eq!(grid.coefficients, new_grid.coefficients);
+        }
+    }
+
+    #[test]
+    fn grid_invalid_texture_count() {
+        let textures = grid().to_volume_textures(VolumeLayout::Packed);
+        assert!(GridCoefficients::from_volume_textures(
+            &textures,
+            VolumeLayout::PerCoefficient,
+            [0.0; 3],
+            [0.0; 3]
+        )
+        .is_err());
+    }
+}
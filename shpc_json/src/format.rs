@@ -0,0 +1,345 @@
+//! Binary and text formats for converting files.
+use ron::extensions::Extensions;
+use ron::ser::PrettyConfig;
+use serde_json::ser::Formatter;
+use shpc::shan::Shan;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+
+// Values nested at least this deep are written on a single line for compact JSON.
+// This is the depth of each element in the TPCB grids.
+const INLINE_DEPTH: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Binary,
+    Json,
+    /// JSON with one grid cell per line.
+    CompactJson,
+    Yaml,
+    Ron,
+    Toml,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "shpc" | "shpcanim" | "binary" => Some(Self::Binary),
+            "json" => Some(Self::Json),
+            "compact" => Some(Self::CompactJson),
+            "yaml" | "yml" => Some(Self::Yaml),
+            "ron" => Some(Self::Ron),
+            "toml" => Some(Self::Toml),
+            _ => None,
+        }
+    }
+
+    /// Detects the format from the extension with `.compact.json` for compact JSON.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let file_name = path.file_name()?.to_str()?;
+        if file_name.ends_with(".compact.json") {
+            return Some(Self::CompactJson);
+        }
+        path.extension()
+            .and_then(|e| e.to_str())
+            .and_then(Self::from_name)
+    }
+
+    /// The file extension without the leading `.` for output files.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Binary => "shpcanim",
+            Self::Json => "json",
+            Self::CompactJson => "compact.json",
+            Self::Yaml => "yaml",
+            Self::Ron => "ron",
+            Self::Toml => "toml",
+        }
+    }
+
+    /// The default format when converting from this format.
+    pub fn converted(self) -> Self {
+        match self {
+            Self::Binary => Self::Json,
+            _ => Self::Binary,
+        }
+    }
+}
+
+// TODO: Create a higher level representation that handles coefficient compression.
+/// Reads `input` or stdin if `input` is `-`.
+pub fn read_shan(input: &str, format: Format) -> Result<Shan, Box<dyn std::error::Error>> {
+    let bytes = if input == "-" {
+        let mut bytes = Vec::new();
+        std::io::stdin().lock().read_to_end(&mut bytes)?;
+        bytes
+    } else {
+        std::fs::read(input)?
+    };
+    from_bytes(&bytes, format)
+}
+
+/// Writes to `output` or stdout if `output` is `-`.
+pub fn write_shan(
+    shan: &Shan,
+    output: &str,
+    format: Format,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let bytes = to_bytes(shan, format)?;
+    if output == "-" {
+        std::io::stdout().lock().write_all(&bytes)?;
+    } else {
+        std::fs::write(output, bytes)?;
+    }
+    Ok(())
+}
+
+pub fn from_bytes(bytes: &[u8], format: Format) -> Result<Shan, Box<dyn std::error::Error>> {
+    match format {
+        Format::Binary => Shan::read(&mut Cursor::new(bytes)),
+        Format::Json | Format::CompactJson => Ok(serde_json::from_slice(bytes)?),
+        Format::Yaml => Ok(serde_yaml::from_slice(bytes)?),
+        Format::Ron => Ok(ron::de::from_bytes(bytes)?),
+        Format::Toml => Ok(toml::from_str(std::str::from_utf8(bytes)?)?),
+    }
+}
+
+pub fn to_bytes(shan: &Shan, format: Format) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    match format {
+        Format::Binary => {
+            // Writing requires seeking, so buffer the output in memory.
+            let mut cursor = Cursor::new(Vec::new());
+            shan.write(&mut cursor)?;
+            Ok(cursor.into_inner())
+        }
+        Format::Json => Ok(serde_json::to_vec_pretty(shan)?),
+        Format::CompactJson => {
+            let mut bytes = Vec::new();
+            let mut serializer =
+                serde_json::Serializer::with_formatter(&mut bytes, CompactGridFormatter::default());
+            serde::Serialize::serialize(shan, &mut serializer)?;
+            Ok(bytes)
+        }
+        Format::Yaml => Ok(serde_yaml::to_string(shan)?.into_bytes()),
+        Format::Ron => {
+            // The flattened TPCB fields can't be deserialized from newtype or Some(...) syntax.
+            let config = PrettyConfig::new()
+                .extensions(Extensions::UNWRAP_NEWTYPES | Extensions::IMPLICIT_SOME);
+            Ok(ron::ser::to_string_pretty(shan, config)?.into_bytes())
+        }
+        Format::Toml => Ok(toml::to_string(shan)?.into_bytes()),
+    }
+}
+
+// Indent like serde_json's PrettyFormatter but write values nested at least INLINE_DEPTH deep on one line.
+#[derive(Default)]
+struct CompactGridFormatter {
+    depth: usize,
+    has_value: bool,
+}
+
+impl CompactGridFormatter {
+    fn is_inline(&self) -> bool {
+        self.depth >= INLINE_DEPTH
+    }
+
+    fn begin<W: ?Sized + Write>(&mut self, writer: &mut W, bracket: &[u8]) -> std::io::Result<()> {
+        self.depth += 1;
+        self.has_value = false;
+        writer.write_all(bracket)
+    }
+
+    fn end<W: ?Sized + Write>(&mut self, writer: &mut W, bracket: &[u8]) -> std::io::Result<()> {
+        let is_inline = self.is_inline();
+        self.depth -= 1;
+        if self.has_value && !is_inline {
+            writer.write_all(b"\n")?;
+            indent(writer, self.depth)?;
+        }
+        writer.write_all(bracket)
+    }
+
+    fn begin_value<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> std::io::Result<()> {
+        if self.is_inline() {
+            writer.write_all(if first { b"" } else { b", " })
+        } else {
+            writer.write_all(if first { b"\n" } else { b",\n" })?;
+            indent(writer, self.depth)
+        }
+    }
+}
+
+impl Formatter for CompactGridFormatter {
+    fn begin_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.begin(writer, b"[")
+    }
+
+    fn end_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.end(writer, b"]")
+    }
+
+    fn begin_array_value<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> std::io::Result<()> {
+        self.begin_value(writer, first)
+    }
+
+    fn end_array_value<W: ?Sized + Write>(&mut self, _writer: &mut W) -> std::io::Result<()> {
+        self.has_value = true;
+        Ok(())
+    }
+
+    fn begin_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.begin(writer, b"{")
+    }
+
+    fn end_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.end(writer, b"}")
+    }
+
+    fn begin_object_key<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> std::io::Result<()> {
+        self.begin_value(writer, first)
+    }
+
+    fn begin_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(b": ")
+    }
+
+    fn end_object_value<W: ?Sized + Write>(&mut self, _writer: &mut W) -> std::io::Result<()> {
+        self.has_value = true;
+        Ok(())
+    }
+}
+
+fn indent<W: ?Sized + Write>(writer: &mut W, depth: usize) -> std::io::Result<()> {
+    for _ in 0..depth {
+        writer.write_all(b"  ")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shpc::{GridCoefficients, GridIndexMode, ShanFile, TpcbData};
+
+    fn shan() -> Shan {
+        // Use values that don't have exact decimal representations.
+        let mut file = ShanFile {
+            name: "chara".to_string(),
+            tpcbs: vec![
+                TpcbData {
+                    starting_frame: 0,
+                    coefficients: GridCoefficients {
+                        grid_cell_count_xyz: [2, 1, 1],
+                        grid_range_min_xyz: [0.1, -1.0 / 3.0, 1e-7],
+                        grid_range_max_xyz: [123456.79, 2.0 / 3.0, f32::MIN_POSITIVE],
+                        unk5: 0.0,
+                        unk6: 0.0,
+                        coefficients: vec![
+                            [[0.1, -0.2, 0.3, 0.7]; 3],
+                            [[-1.0 / 3.0, 0.0, 1e-3, 1.1]; 3],
+                        ],
+                    },
+                },
+                TpcbData {
+                    starting_frame: 30,
+                    coefficients: GridCoefficients {
+                        grid_cell_count_xyz: [1, 1, 1],
+                        grid_range_min_xyz: [f32::MAX, -0.0, 0.3],
+                        grid_range_max_xyz: [f32::MAX, -0.0, 0.3],
+                        unk5: 0.0,
+                        unk6: 0.0,
+                        coefficients: vec![[[0.5; 4]; 3]],
+                    },
+                },
+            ],
+        };
+        for tpcb in &mut file.tpcbs {
+            tpcb.coefficients.recalculate_unk5_unk6();
+        }
+        file.to_shan(GridIndexMode::Identity).unwrap()
+    }
+
+    fn round_trip(format: Format) {
+        let shan = shan();
+        let bytes = to_bytes(&shan, format).unwrap();
+        let result = from_bytes(&bytes, format).unwrap();
+        assert_eq!(shan, result, "{}", String::from_utf8_lossy(&bytes));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        round_trip(Format::Json);
+    }
+
+    #[test]
+    fn compact_json_round_trip() {
+        round_trip(Format::CompactJson);
+    }
+
+    #[test]
+    fn yaml_round_trip() {
+        round_trip(Format::Yaml);
+    }
+
+    #[test]
+    fn ron_round_trip() {
+        round_trip(Format::Ron);
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        round_trip(Format::Toml);
+    }
+
+    #[test]
+    fn compact_json_cell_per_line() {
+        let bytes = to_bytes(&shan(), Format::CompactJson).unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+
+        // Each compressed coefficient record should be on a single line.
+        let lines: Vec<_> = text.lines().filter(|l| l.contains("\"r\"")).collect();
+        assert_eq!(3, lines.len());
+        assert!(lines.iter().all(|l| l.contains("\"g\"")
+            && l.contains("\"b\"")
+            && l.trim_end().ends_with(['}', ','])));
+
+        // The layout shouldn't change the values.
+        let json = to_bytes(&shan(), Format::Json).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&json).unwrap(),
+            serde_json::from_str::<serde_json::Value>(&text).unwrap()
+        );
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(
+            Some(Format::Binary),
+            Format::from_path(Path::new("chara.shpcanim"))
+        );
+        assert_eq!(
+            Some(Format::Json),
+            Format::from_path(Path::new("chara.shpcanim.json"))
+        );
+        assert_eq!(
+            Some(Format::CompactJson),
+            Format::from_path(Path::new("chara.compact.json"))
+        );
+        assert_eq!(Some(Format::Yaml), Format::from_path(Path::new("a/b.yml")));
+        assert_eq!(Some(Format::Ron), Format::from_path(Path::new("b.ron")));
+        assert_eq!(Some(Format::Toml), Format::from_path(Path::new("b.toml")));
+        assert_eq!(None, Format::from_path(Path::new("b.txt")));
+    }
+}
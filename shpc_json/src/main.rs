@@ -1,41 +1,507 @@
+mod format;
+
+use format::{read_shan, write_shan, Format};
+use rayon::prelude::*;
+use shpc::quantization::AXIS_NORMALS;
+use shpc::sh::{parse_renderdoc_float4s, CompressionSample};
 use shpc::shan::Shan;
+use shpc::ShanFile;
+use std::collections::HashMap;
 use std::env;
-use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::time::Instant;
 
-// TODO: Create a higher level representation that handles coefficient compression.
-fn parse_and_write_json<P: AsRef<Path>>(input: P, output: P) {
-    let parse_start_time = Instant::now();
-    match Shan::from_file(&input) {
-        Ok(data) => {
-            eprintln!("Parse: {:?}", parse_start_time.elapsed());
+// Print rows for test_data/coeffs.csv from a RenderDoc buffer export.
+// The compressed bytes are the values from the file for each float4 in the buffer.
+fn print_renderdoc_rows(args: &[String]) {
+    if args.len() < 3 {
+        eprintln!("Usage:");
+        eprintln!("\tshpc_data_json renderdoc <buffer txt> <unk5> <unk6> <compressed bytes...>");
+        return;
+    }
+
+    let text = std::fs::read_to_string(&args[0]).expect("Failed to read file.");
+    let float4s = parse_renderdoc_float4s(&text);
+    let unk5: f32 = args[1].parse().expect("Failed to parse unk5.");
+    let unk6: f32 = args[2].parse().expect("Failed to parse unk6.");
+    let bytes: Vec<u8> = args[3..]
+        .iter()
+        .map(|b| b.parse().expect("Failed to parse compressed byte."))
+        .collect();
+
+    if bytes.len() != float4s.len() * 4 {
+        eprintln!(
+            "Expected {} compressed bytes for {} float4 values but found {}",
+            float4s.len() * 4,
+            float4s.len(),
+            bytes.len()
+        );
+        return;
+    }
+
+    for (coefficients, compressed) in float4s.iter().zip(bytes.chunks_exact(4)) {
+        let sample = CompressionSample {
+            compressed: compressed.try_into().unwrap(),
+            unk5,
+            unk6,
+            coefficients: *coefficients,
+        };
+        println!("{}", sample.to_csv_row());
+    }
+}
+
+fn print_info(args: &[String]) {
+    let input = match args.first() {
+        Some(input) => input,
+        None => {
+            eprintln!("Usage:");
+            eprintln!("\tshpc_data_json info <file>");
+            return;
+        }
+    };
+
+    let shan = Shan::from_file(input).expect("Failed to read file.");
+    let file = ShanFile::try_from(&shan).expect("Failed to convert file.");
+
+    println!("name: {}", file.name);
+    println!("TPCB count: {}", file.tpcbs.len());
+    for (i, tpcb) in file.tpcbs.iter().enumerate() {
+        let grid = &tpcb.coefficients;
+        println!("TPCB {} (frame {})", i, tpcb.starting_frame);
+        println!("  cell counts: {:?}", grid.grid_cell_count_xyz);
+        println!("  range min: {:?}", grid.grid_range_min_xyz);
+        println!("  range max: {:?}", grid.grid_range_max_xyz);
+        println!("  unk5: {}, unk6: {}", grid.unk5, grid.unk6);
+        if let Some(decomposition) = grid.average_light() {
+            println!("  ambient: {:?}", decomposition.ambient);
+            println!("  light direction: {:?}", decomposition.light.direction);
+            println!("  light color: {:?}", decomposition.light.color);
+            println!("  light intensity: {}", decomposition.light.intensity);
+        }
+    }
+}
+
+fn print_stats(args: &[String]) {
+    let input = match args.first() {
+        Some(input) => input,
+        None => {
+            eprintln!("Usage:");
+            eprintln!("\tshpc_data_json stats <file> [--json]");
+            return;
+        }
+    };
+
+    let shan = Shan::from_file(input).expect("Failed to read file.");
+    let file = ShanFile::try_from(&shan).expect("Failed to convert file.");
+    let stats = file.stats();
+
+    if args.iter().any(|a| a == "--json") {
+        println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+        return;
+    }
+
+    println!("name: {}", stats.name);
+    println!(
+        "{:>5} {:>7} {:>15} {:>30} {:>30} {:>30} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10} {:>24}",
+        "tpcb",
+        "frame",
+        "cells",
+        "min",
+        "max",
+        "spacing",
+        "unk5",
+        "unk6",
+        "l0 min",
+        "l0 max",
+        "l0 mean",
+        "l1 length",
+        "l1 direction"
+    );
+    for (i, tpcb) in stats.tpcbs.iter().enumerate() {
+        let lighting = &tpcb.lighting;
+        println!(
+            "{:>5} {:>7} {:>15} {:>30} {:>30} {:>30} {:>10.4} {:>10.4} {:>10.4} {:>10.4} {:>10.4} {:>10.4} {:>24}",
+            i,
+            tpcb.starting_frame,
+            format!("{:?}", tpcb.grid_cell_count_xyz),
+            format_xyz(tpcb.grid_range_min_xyz),
+            format_xyz(tpcb.grid_range_max_xyz),
+            format_xyz(tpcb.grid_spacing_xyz),
+            tpcb.unk5,
+            tpcb.unk6,
+            lighting.l0_luminance.min,
+            lighting.l0_luminance.max,
+            lighting.l0_luminance.mean,
+            lighting.l1_magnitude,
+            format_xyz(lighting.l1_direction)
+        );
+    }
+
+    println!();
+    println!("{:>12} {:>10} {:>10}", "coefficient", "min", "max");
+    for (channel, channel_name) in ["r", "g", "b"].iter().enumerate() {
+        for (coefficient, name) in ["l1_x", "l1_y", "l1_z", "l0"].iter().enumerate() {
+            println!(
+                "{:>12} {:>10.4} {:>10.4}",
+                format!("{}_{}", channel_name, name),
+                stats.lighting.coefficient_min[channel][coefficient],
+                stats.lighting.coefficient_max[channel][coefficient]
+            );
+        }
+    }
+}
+
+fn format_xyz(values: [f32; 3]) -> String {
+    format!("[{:.3}, {:.3}, {:.3}]", values[0], values[1], values[2])
+}
+
+// Arguments that aren't options or the values for options.
+fn positional_args<'a>(args: &'a [String], options_with_values: &[&str]) -> Vec<&'a String> {
+    let mut positionals = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if options_with_values.contains(&arg.as_str()) {
+            args.next();
+        } else if !arg.starts_with("--") {
+            positionals.push(arg);
+        }
+    }
+    positionals
+}
+
+fn print_diff(args: &[String]) {
+    let files = positional_args(args, &["--tolerance", "--heat-map"]);
+    if files.len() != 2 {
+        eprintln!("Usage:");
+        eprintln!(
+            "\tshpc_data_json diff <old file> <new file> [--tolerance <value>] [--heat-map <directory>]"
+        );
+        return;
+    }
+
+    let option = |name: &str| {
+        args.iter()
+            .position(|a| a == name)
+            .and_then(|i| args.get(i + 1))
+    };
+    let tolerance: f32 = option("--tolerance")
+        .map(|t| t.parse().expect("Failed to parse tolerance."))
+        .unwrap_or(0.0);
+    if tolerance.is_nan() || tolerance < 0.0 {
+        eprintln!("The tolerance must be a non negative number.");
+        std::process::exit(1);
+    }
+
+    let old = Shan::from_file(files[0]).expect("Failed to read file.");
+    let new = Shan::from_file(files[1]).expect("Failed to read file.");
+    let diff = old.diff(&new, tolerance).expect("Failed to convert file.");
+
+    if diff.is_empty() {
+        println!("No differences");
+        return;
+    }
+
+    if let Some((old_name, new_name)) = &diff.name {
+        println!("name: {} -> {}", old_name, new_name);
+    }
+    for change in &diff.header {
+        println!("{}", change);
+    }
+    for change in &diff.timeline {
+        println!("{}", change);
+    }
+    for tpcb in &diff.tpcbs {
+        if tpcb.is_empty() {
+            continue;
+        }
+
+        println!(
+            "TPCB {} -> {} (frame {})",
+            tpcb.old_index, tpcb.new_index, tpcb.starting_frame
+        );
+        for field in &tpcb.fields {
+            println!("  {}", field);
+        }
+        if !tpcb.cell_differences.is_empty() {
+            println!(
+                "  changed cells: {} of {} (max difference {})",
+                tpcb.changed_cells.len(),
+                tpcb.cell_differences.len(),
+                tpcb.max_difference()
+            );
+        }
+    }
+
+    if let Some(directory) = option("--heat-map") {
+        std::fs::create_dir_all(directory).expect("Failed to create directory.");
+        for tpcb in &diff.tpcbs {
+            if let Some(image) = tpcb.heat_map() {
+                let path = Path::new(directory).join(format!("tpcb_{}.png", tpcb.new_index));
+                image
+                    .write_to_file(path)
+                    .expect("Failed to write heat map.");
+            }
+        }
+    }
+}
+
+fn print_layout(args: &[String]) {
+    let input = match args.first() {
+        Some(input) => input,
+        None => {
+            eprintln!("Usage:");
+            eprintln!("\tshpc_data_json layout <file> [--full]");
+            return;
+        }
+    };
+
+    let bytes = std::fs::read(input).expect("Failed to read file.");
+    let ranges = shpc::layout::layout(&bytes).expect("Failed to find layout.");
+    // Limit the hex output for large grids unless requested.
+    let max_lines = if args.iter().any(|a| a == "--full") {
+        usize::MAX
+    } else {
+        4
+    };
+    let stdout = std::io::stdout();
+    shpc::layout::write_layout(&mut stdout.lock(), &bytes, &ranges, max_lines)
+        .expect("Failed to write layout.");
+}
+
+// Find files matching is_match in path and any subdirectories except exclude.
+fn find_files(
+    path: &Path,
+    is_match: fn(&Path) -> bool,
+    exclude: Option<&Path>,
+) -> std::io::Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if exclude.is_none() || std::fs::canonicalize(&path).ok().as_deref() != exclude {
+                files.extend(find_files(&path, is_match, exclude)?);
+            }
+        } else if is_match(&path) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn is_binary_file(path: &Path) -> bool {
+    Format::from_path(path) == Some(Format::Binary)
+}
+
+// The binary file name for text files like chara.shpcanim.json or chara.shpc.compact.json.
+fn binary_file_name(path: &Path) -> Option<&str> {
+    let stem = path.file_stem()?.to_str()?.trim_end_matches(".compact");
+    is_binary_file(Path::new(stem)).then_some(stem)
+}
 
-            let json = serde_json::to_string_pretty(&data).unwrap();
+fn is_convertible_file(path: &Path) -> bool {
+    is_binary_file(path) || (Format::from_path(path).is_some() && binary_file_name(path).is_some())
+}
+
+// The output path for converting a binary file to JSON or a JSON file back to binary.
+fn converted_path(input: &Path, output_directory: &Path) -> Result<PathBuf, String> {
+    let file_name = input
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or("Invalid file name")?;
+
+    match Format::from_path(input).ok_or("Unsupported extension")? {
+        Format::Binary => Ok(output_directory.join(format!("{}.json", file_name))),
+        // Restore the original extension for files like chara.shpcanim.json.
+        _ => binary_file_name(input)
+            .map(|name| output_directory.join(name))
+            .ok_or_else(|| "Expected a name like chara.shpcanim.json".to_string()),
+    }
+}
 
-            let mut output_file = std::fs::File::create(output).expect("unable to create file");
-            output_file
-                .write_all(json.as_bytes())
-                .expect("unable to write");
+fn convert_file(input: &Path, output: &Path) -> Result<(), String> {
+    let format = Format::from_path(input).ok_or("Unsupported extension")?;
+    let shan = read_shan(&input.to_string_lossy(), format).map_err(|e| e.to_string())?;
+    write_shan(&shan, &output.to_string_lossy(), format.converted()).map_err(|e| e.to_string())
+}
+
+// Convert every file in input to output in parallel with the same folder structure.
+fn convert_directory(input: &Path, output: &Path) {
+    // Don't convert previously converted files if output is inside input.
+    let exclude = std::fs::canonicalize(output).ok();
+    let files = match find_files(input, is_convertible_file, exclude.as_deref()) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("Failed to find files in {}: {}", input.display(), e);
+            std::process::exit(1);
         }
-        Err(error) => eprintln!("{:?}", error),
     };
+
+    let start_time = Instant::now();
+    let outputs: Vec<_> = files
+        .iter()
+        .map(|path| {
+            let relative = path.strip_prefix(input).unwrap_or(path);
+            let output_directory = output.join(relative.parent().unwrap_or(Path::new("")));
+            converted_path(path, &output_directory)
+        })
+        .collect();
+
+    // Files like chara.shpcanim.json and chara.shpcanim.yaml would overwrite the same output.
+    let mut output_counts = HashMap::new();
+    for output in outputs.iter().flatten() {
+        *output_counts.entry(output).or_insert(0) += 1;
+    }
+
+    let results: Vec<_> = files
+        .par_iter()
+        .zip(&outputs)
+        .map(|(path, output)| {
+            let result = output.clone().and_then(|output| {
+                if output_counts[&output] > 1 {
+                    return Err(format!(
+                        "{} is the output for multiple files",
+                        output.display()
+                    ));
+                }
+                if let Some(parent) = output.parent() {
+                    std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+                }
+                convert_file(path, &output).map(|_| output)
+            });
+            (path, result)
+        })
+        .collect();
+
+    let mut failures = 0;
+    for (path, result) in &results {
+        match result {
+            Ok(output) => println!("OK {} -> {}", path.display(), output.display()),
+            Err(e) => {
+                failures += 1;
+                println!("ERROR {}: {}", path.display(), e);
+            }
+        }
+    }
+    println!(
+        "Converted {} of {} files in {:?}",
+        results.len() - failures,
+        results.len(),
+        start_time.elapsed()
+    );
+    if failures > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn verify_round_trips(args: &[String]) {
+    let input = match args.first() {
+        Some(input) => input,
+        None => {
+            eprintln!("Usage:");
+            eprintln!("\tshpc_data_json verify <file or directory>");
+            return;
+        }
+    };
+
+    let files = match find_files(Path::new(input), is_binary_file, None) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("Failed to find files in {}: {}", input, e);
+            std::process::exit(1);
+        }
+    };
+    let mut failures = 0;
+    for path in &files {
+        let result = std::fs::read(path)
+            .map_err(Into::into)
+            .and_then(|bytes| shpc::round_trip::verify_round_trip(&bytes));
+        match result {
+            Ok(None) => println!("OK {}", path.display()),
+            Ok(Some(mismatch)) => {
+                failures += 1;
+                println!("MISMATCH {}: {}", path.display(), mismatch);
+            }
+            Err(e) => {
+                failures += 1;
+                println!("ERROR {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    println!(
+        "{} of {} files round trip exactly",
+        files.len() - failures,
+        files.len()
+    );
+    if failures > 0 {
+        std::process::exit(1);
+    }
 }
 
-fn deserialize_and_save(
-    json: &str,
-    input: &Path,
-    output: &Option<PathBuf>,
-    extension: &str,
-) -> serde_json::Result<()> {
-    let data = serde_json::from_str::<Shan>(json)?;
+fn print_quantization_report(args: &[String]) {
+    let input = match args.first() {
+        Some(input) => input,
+        None => {
+            eprintln!("Usage:");
+            eprintln!("\tshpc_data_json quantization <file>");
+            return;
+        }
+    };
+
+    let shan = Shan::from_file(input).expect("Failed to read file.");
+    let file = ShanFile::try_from(&shan).expect("Failed to convert file.");
+
+    for (i, (tpcb, report)) in file
+        .tpcbs
+        .iter()
+        .zip(file.quantization_reports(&AXIS_NORMALS))
+        .enumerate()
+    {
+        println!(
+            "TPCB {} (frame {}, {} cells)",
+            i,
+            tpcb.starting_frame,
+            report.cells.len()
+        );
+        // Values from the file round trip exactly with the current unk5 and unk6,
+        // so also show the error from refitting and re-encoding the grid.
+        let refit = tpcb.coefficients.refit_quantization_report(&AXIS_NORMALS);
+        println!("  (current, refit)");
+        println!(
+            "  max coefficient error: {}, {}",
+            report.max_coefficient_error, refit.max_coefficient_error
+        );
+        println!(
+            "  mean coefficient error: {}, {}",
+            report.mean_coefficient_error, refit.mean_coefficient_error
+        );
+        println!(
+            "  max irradiance error: {}, {}",
+            report.max_irradiance_error, refit.max_irradiance_error
+        );
+        println!(
+            "  rms irradiance error: {}, {}",
+            report.rms_irradiance_error, refit.rms_irradiance_error
+        );
+        println!("  psnr: {} dB, {} dB", report.psnr, refit.psnr);
+
+        let limit_cells: Vec<_> = report.limit_cells().map(|c| c.index).collect();
+        println!("  cells using 0 or 255: {:?}", limit_cells);
 
-    let output_path = output
-        .as_ref()
-        .map(PathBuf::from)
-        .unwrap_or_else(|| PathBuf::from(input).with_extension(extension));
-    data.write_to_file(output_path).unwrap();
-    Ok(())
+        println!("  compressed values (min, max, distinct):");
+        for (channel, channel_name) in ["r", "g", "b"].iter().enumerate() {
+            for (coefficient, name) in ["l1_x", "l1_y", "l1_z", "l0"].iter().enumerate() {
+                if let Some((min, max, count)) = report.byte_usage(channel, coefficient) {
+                    println!("    {}_{}: {}, {}, {}", channel_name, name, min, max, count);
+                }
+            }
+        }
+    }
 }
 
 fn main() {
@@ -44,26 +510,129 @@ fn main() {
         eprintln!("Usage:");
         eprintln!("\tshpc_data_json <file>");
         eprintln!("\tshpc_data_json <file> <json output>");
+        eprintln!("\tshpc_data_json <directory> <output directory>");
+        eprintln!("\tshpc_data_json <file or -> <output or -> [--from <format>] [--to <format>]");
+        eprintln!("\t\tformats: shpc, shpcanim, binary, json, compact, yaml, yml, ron, toml");
+        eprintln!("\t\tcompact is JSON with one grid cell per line detected from .compact.json");
+        eprintln!("\tshpc_data_json renderdoc <buffer txt> <unk5> <unk6> <compressed bytes...>");
+        eprintln!("\tshpc_data_json quantization <file>");
+        eprintln!("\tshpc_data_json info <file>");
+        eprintln!("\tshpc_data_json stats <file> [--json]");
+        eprintln!(
+            "\tshpc_data_json diff <old file> <new file> [--tolerance <value>] [--heat-map <directory>]"
+        );
+        eprintln!("\tshpc_data_json layout <file> [--full]");
+        eprintln!("\tshpc_data_json verify <file or directory>");
         return;
     }
 
-    let input = args.get(1).unwrap();
-    let input_path = Path::new(&input);
-    // Modify the input if no output is specified to allow dragging a file onto the executable.
-    let output_path = args
-        .get(2)
-        .map(PathBuf::from)
-        .unwrap_or_else(|| PathBuf::from(input.to_string() + ".json"));
+    match args[1].as_str() {
+        "renderdoc" => {
+            print_renderdoc_rows(&args[2..]);
+            return;
+        }
+        "quantization" => {
+            print_quantization_report(&args[2..]);
+            return;
+        }
+        "info" => {
+            print_info(&args[2..]);
+            return;
+        }
+        "stats" => {
+            print_stats(&args[2..]);
+            return;
+        }
+        "diff" => {
+            print_diff(&args[2..]);
+            return;
+        }
+        "layout" => {
+            print_layout(&args[2..]);
+            return;
+        }
+        "verify" => {
+            verify_round_trips(&args[2..]);
+            return;
+        }
+        _ => (),
+    }
 
-    // Try parsing one of the supported formats.
-    match input_path.extension().unwrap().to_str().unwrap() {
-        "shpcanim" => parse_and_write_json(input_path, &output_path),
-        "json" => {
-            let json = std::fs::read_to_string(input_path).expect("Failed to read file.");
-            let output_path = args.get(2).map(PathBuf::from);
+    let positionals = positional_args(&args[1..], &["--from", "--to"]);
+    let input = match positionals.first() {
+        Some(input) => *input,
+        None => {
+            eprintln!("Usage:");
+            eprintln!(
+                "\tshpc_data_json <file or -> <output or -> [--from <format>] [--to <format>]"
+            );
+            return;
+        }
+    };
+    let input_path = Path::new(input);
+    if input_path.is_dir() {
+        match positionals.get(1) {
+            Some(output) => convert_directory(input_path, Path::new(output)),
+            None => {
+                eprintln!("Usage:");
+                eprintln!("\tshpc_data_json <directory> <output directory>");
+            }
+        }
+        return;
+    }
 
-            deserialize_and_save(&json, input_path, &output_path, "shpcanim").unwrap();
+    let option = |name: &str| {
+        args.iter()
+            .position(|a| a == name)
+            .and_then(|i| args.get(i + 1))
+    };
+    let input_format = match option("--from") {
+        Some(name) => Format::from_name(name),
+        None => Format::from_path(input_path),
+    };
+    let input_format = match input_format {
+        Some(format) => format,
+        None => {
+            eprintln!(
+                "Unable to determine the input format. Specify the format with --from <format>."
+            );
+            return;
         }
-        _ => (),
     };
+    let output_format = match option("--to").map(|name| Format::from_name(name)) {
+        Some(Some(format)) => format,
+        Some(None) => {
+            eprintln!("Unsupported output format.");
+            return;
+        }
+        None => input_format.converted(),
+    };
+
+    // Modify the input if no output is specified to allow dragging a file onto the executable.
+    let output = match positionals.get(1) {
+        Some(output) => output.to_string(),
+        None if input == "-" => "-".to_string(),
+        None => match output_format {
+            // Restore the original extension for files like chara.shpc.json.
+            Format::Binary => match binary_file_name(input_path) {
+                Some(name) => input_path.with_file_name(name),
+                None => input_path.with_extension("shpcanim"),
+            }
+            .to_string_lossy()
+            .to_string(),
+            _ => format!("{}.{}", input, output_format.extension()),
+        },
+    };
+
+    let parse_start_time = Instant::now();
+    let shan = match read_shan(input, input_format) {
+        Ok(shan) => shan,
+        Err(error) => {
+            eprintln!("{:?}", error);
+            return;
+        }
+    };
+    eprintln!("Parse: {:?}", parse_start_time.elapsed());
+
+    write_shan(&shan, &output, output_format).expect("Failed to write output.");
 }